@@ -0,0 +1,64 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use rustte::Row;
+
+const LONG_LINE: usize = 100_000;
+
+fn long_line() -> String {
+    "lorem ipsum dolor sit amet, é 👍 ".repeat(LONG_LINE / 32)
+}
+
+fn editing(c: &mut Criterion) {
+    let line = long_line();
+    let len = Row::from(&line[..]).len();
+
+    c.bench_function("insert at end of long line", |b| {
+        b.iter_batched_ref(
+            || Row::from(&line[..]),
+            |row| row.insert(black_box(len.saturating_sub(1)), 'x'),
+            BatchSize::LargeInput,
+        );
+    });
+    c.bench_function("type 100 chars at end of long line", |b| {
+        b.iter_batched_ref(
+            || Row::from(&line[..]),
+            |row| {
+                for at in 0..100 {
+                    row.insert(black_box(len.saturating_sub(1).saturating_add(at)), 'x');
+                }
+            },
+            BatchSize::LargeInput,
+        );
+    });
+    c.bench_function("delete at end of long line", |b| {
+        b.iter_batched_ref(
+            || Row::from(&line[..]),
+            |row| row.delete(black_box(len.saturating_sub(2))),
+            BatchSize::LargeInput,
+        );
+    });
+    c.bench_function("split long line in the middle", |b| {
+        b.iter_batched_ref(
+            || Row::from(&line[..]),
+            |row| row.split(black_box(len / 2)),
+            BatchSize::LargeInput,
+        );
+    });
+}
+
+fn reading(c: &mut Criterion) {
+    let row = Row::from(&long_line()[..]);
+    let len = row.len();
+
+    c.bench_function("render screen width at end of long line", |b| {
+        b.iter(|| row.render(black_box(len.saturating_sub(80)), black_box(len), 0, 4));
+    });
+    c.bench_function("screen column at end of long line", |b| {
+        b.iter(|| row.column(black_box(len), 4));
+    });
+    c.bench_function("find in long line", |b| {
+        b.iter(|| row.find(black_box("sit amet, é 👍 lorem")));
+    });
+}
+
+criterion_group!(benches, editing, reading);
+criterion_main!(benches);
@@ -0,0 +1,337 @@
+use crate::frame::{Frame, Style};
+use crate::Position;
+use std::collections::VecDeque;
+use std::io::{self, stdout, Read, Stdout, Write};
+use std::panic;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use termion::color;
+use termion::event::{Event, Key};
+use termion::input::TermRead;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{AlternateScreen, ToAlternateScreen, ToMainScreen};
+
+type Screen = AlternateScreen<RawTerminal<Stdout>>;
+
+// termion has no keys for these, so they travel as Ctrl with a character
+// no keyboard sends that way.
+pub const CTRL_LEFT: Key = Key::Ctrl('←');
+pub const CTRL_RIGHT: Key = Key::Ctrl('→');
+pub const CTRL_BACKSPACE: Key = Key::Ctrl('⌫');
+pub const CTRL_DELETE: Key = Key::Ctrl('⌦');
+
+// Unchanged cells worth rewriting to save a cursor move, which takes about
+// as many bytes.
+const SHORT_GAP: usize = 4;
+
+// The key for an input event, picking the keys termion doesn't know out of
+// their escape sequences. Mouse events are dropped.
+fn translate(event: Event) -> Option<Key> {
+    match event {
+        // Esc-DEL is Alt-Backspace. ^H is left alone, since many terminals
+        // send it for a plain Backspace.
+        Event::Key(Key::Alt('\x7f')) => Some(CTRL_BACKSPACE),
+        Event::Key(key) => Some(key),
+        // xterm style with Ctrl (5) or Alt (3), then rxvt style; Backspace
+        // only comes with modifiers as xterm's modifyOtherKeys or the
+        // fixterms form.
+        Event::Unsupported(bytes) => match &bytes[..] {
+            b"\x1b[1;5D" | b"\x1b[1;3D" | b"\x1bOd" => Some(CTRL_LEFT),
+            b"\x1b[1;5C" | b"\x1b[1;3C" | b"\x1bOc" => Some(CTRL_RIGHT),
+            b"\x1b[3;5~" | b"\x1b[3;3~" | b"\x1b[3^" => Some(CTRL_DELETE),
+            b"\x1b[27;5;127~" | b"\x1b[27;3;127~" | b"\x1b[127;5u" | b"\x1b[127;3u" => Some(CTRL_BACKSPACE),
+            _ => None,
+        },
+        Event::Mouse(_) => None,
+    }
+}
+
+// Where the editor's screen goes and its keys come from.
+pub trait Backend {
+    // Width and height of the whole screen, in cells.
+    fn size(&self) -> Result<(usize, usize), io::Error>;
+
+    fn read_key(&mut self, timeout: Duration) -> Result<Option<Key>, io::Error>;
+
+    // Brings the screen up to date with `frame`. `previous` is what the
+    // screen shows now, if known.
+    fn draw(&mut self, frame: &Frame, previous: Option<&Frame>) -> Result<(), io::Error>;
+
+    // Shows the cursor at `position`, or hides it.
+    fn set_cursor(&mut self, position: Option<Position>) -> Result<(), io::Error>;
+
+    fn flush(&mut self) -> Result<(), io::Error>;
+
+    // Hands the screen back to the shell until the user resumes us.
+    fn suspend(&mut self) -> Result<(), io::Error>;
+}
+
+pub struct TermionBackend {
+    // Dropping the screen leaves the alternate screen and raw mode. It is
+    // shared with the panic hook so a panic can do that before reporting.
+    screen: Arc<Mutex<Option<Screen>>>,
+    keys: Receiver<Result<Key, io::Error>>,
+    out: Vec<u8>,
+}
+
+impl TermionBackend {
+    pub fn new() -> Result<Self, io::Error> {
+        // With a document piped in on stdin, keys come from the terminal
+        // itself.
+        let input: Box<dyn Read + Send> = if termion::is_tty(&io::stdin()) {
+            Box::new(io::stdin())
+        } else {
+            Box::new(termion::get_tty()?)
+        };
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            for event in input.events() {
+                let key = match event {
+                    Ok(event) => match translate(event) {
+                        Some(key) => Ok(key),
+                        None => continue,
+                    },
+                    Err(error) => Err(error),
+                };
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        let screen = Arc::new(Mutex::new(Some(AlternateScreen::from(stdout().into_raw_mode()?))));
+        let panic_screen = Arc::clone(&screen);
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if let Ok(mut screen) = panic_screen.try_lock() {
+                if screen.take().is_some() {
+                    show_cursor();
+                }
+            }
+            default_hook(info);
+        }));
+        Ok(Self {
+            screen,
+            keys,
+            out: Vec::new(),
+        })
+    }
+
+    fn screen(&self) -> Result<MutexGuard<'_, Option<Screen>>, io::Error> {
+        self.screen
+            .lock()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "terminal state lost"))
+    }
+}
+
+impl Backend for TermionBackend {
+    fn size(&self) -> Result<(usize, usize), io::Error> {
+        let (width, height) = termion::terminal_size()?;
+        Ok((width as usize, height as usize))
+    }
+
+    fn read_key(&mut self, timeout: Duration) -> Result<Option<Key>, io::Error> {
+        match self.keys.recv_timeout(timeout) {
+            Ok(key) => key.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "input closed",
+            )),
+        }
+    }
+
+    fn draw(&mut self, frame: &Frame, previous: Option<&Frame>) -> Result<(), io::Error> {
+        let blank;
+        let previous = if let Some(previous) = previous {
+            previous
+        } else {
+            write!(self.out, "{}", termion::clear::All)?;
+            blank = Frame::new(frame.width(), frame.height());
+            &blank
+        };
+        write!(self.out, "{}", termion::cursor::Hide)?;
+        let mut at = None;
+        let mut style = None;
+        for (x, y, cell) in frame.changes(previous) {
+            if let Some(gap) = at.and_then(|at| gap(frame, at, (x, y), style)) {
+                self.out.extend_from_slice(gap.as_bytes());
+            } else if at != Some((x, y)) {
+                write!(self.out, "{}", goto(x, y))?;
+            }
+            if style != Some(cell.style) {
+                match cell.style.fg {
+                    Some(fg) => write!(self.out, "{}", color::Fg(fg))?,
+                    None => write!(self.out, "{}", color::Fg(color::Reset))?,
+                }
+                match cell.style.bg {
+                    Some(bg) => write!(self.out, "{}", color::Bg(bg))?,
+                    None => write!(self.out, "{}", color::Bg(color::Reset))?,
+                }
+                style = Some(cell.style);
+            }
+            self.out.extend_from_slice(cell.grapheme.as_bytes());
+            // Wide graphemes may take more than one column on screen, so
+            // don't rely on where the terminal put the cursor after them.
+            at = cell.grapheme.is_ascii().then(|| (x.saturating_add(1), y));
+        }
+        if style.is_some() {
+            write!(self.out, "{}{}", color::Fg(color::Reset), color::Bg(color::Reset))?;
+        }
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, position: Option<Position>) -> Result<(), io::Error> {
+        match position {
+            Some(position) => write!(
+                self.out,
+                "{}{}",
+                goto(position.x, position.y),
+                termion::cursor::Show
+            ),
+            None => write!(self.out, "{}", termion::cursor::Hide),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&self.out)?;
+        self.out.clear();
+        stdout.flush()
+    }
+
+    // Returns once the shell resumes us, with raw mode and the alternate
+    // screen restored.
+    fn suspend(&mut self) -> Result<(), io::Error> {
+        {
+            let mut screen = self.screen()?;
+            if let Some(screen) = screen.as_mut() {
+                write!(screen, "{}{}", ToMainScreen, termion::cursor::Show)?;
+                screen.flush()?;
+                screen.suspend_raw_mode()?;
+            }
+        }
+        // SAFETY: raise only delivers a signal to the calling thread. SIGTSTP
+        // stops the whole process, so this returns after SIGCONT.
+        unsafe {
+            libc::raise(libc::SIGTSTP);
+        }
+        let mut screen = self.screen()?;
+        if let Some(screen) = screen.as_mut() {
+            screen.activate_raw_mode()?;
+            write!(screen, "{}{}", ToAlternateScreen, termion::clear::All)?;
+            screen.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TermionBackend {
+    fn drop(&mut self) {
+        show_cursor();
+        if let Ok(mut screen) = self.screen() {
+            screen.take();
+        }
+    }
+}
+
+// The unchanged cells between `from` and `to` when rewriting them is cheaper
+// than moving the cursor over them.
+fn gap(frame: &Frame, from: (usize, usize), to: (usize, usize), style: Option<Style>) -> Option<String> {
+    if from.1 != to.1 || to.0 < from.0 || to.0.saturating_sub(from.0) > SHORT_GAP {
+        return None;
+    }
+    (from.0..to.0)
+        .map(|x| {
+            frame
+                .cell(x, from.1)
+                .filter(|cell| Some(cell.style) == style && cell.grapheme.is_ascii())
+                .map(|cell| cell.grapheme.as_str())
+        })
+        .collect()
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn goto(x: usize, y: usize) -> termion::cursor::Goto {
+    termion::cursor::Goto(x.saturating_add(1) as u16, y.saturating_add(1) as u16)
+}
+
+fn show_cursor() {
+    print!("{}", termion::cursor::Show);
+    let _ = io::stdout().flush();
+}
+
+// An in-memory screen fed from a fixed list of keys, for driving the editor
+// without a terminal. Input runs dry with an `UnexpectedEof` error.
+pub struct HeadlessBackend {
+    screen: Frame,
+    cursor: Option<Position>,
+    keys: VecDeque<Key>,
+}
+
+impl HeadlessBackend {
+    pub fn new(width: usize, height: usize, keys: impl IntoIterator<Item = Key>) -> Self {
+        Self {
+            screen: Frame::new(width, height),
+            cursor: None,
+            keys: keys.into_iter().collect(),
+        }
+    }
+
+    pub fn push_keys(&mut self, keys: impl IntoIterator<Item = Key>) {
+        self.keys.extend(keys);
+    }
+
+    pub fn screen(&self) -> &Frame {
+        &self.screen
+    }
+
+    // The screen as text, one string per row with trailing blanks removed.
+    pub fn lines(&self) -> Vec<String> {
+        (0..self.screen.height())
+            .map(|y| {
+                let line: String = (0..self.screen.width())
+                    .filter_map(|x| self.screen.cell(x, y))
+                    .map(|cell| cell.grapheme.as_str())
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    pub fn cursor(&self) -> Option<Position> {
+        self.cursor
+    }
+}
+
+impl Backend for HeadlessBackend {
+    fn size(&self) -> Result<(usize, usize), io::Error> {
+        Ok((self.screen.width(), self.screen.height()))
+    }
+
+    fn read_key(&mut self, _timeout: Duration) -> Result<Option<Key>, io::Error> {
+        self.keys
+            .pop_front()
+            .map(Some)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "input closed"))
+    }
+
+    fn draw(&mut self, frame: &Frame, _previous: Option<&Frame>) -> Result<(), io::Error> {
+        self.screen = frame.clone();
+        Ok(())
+    }
+
+    fn set_cursor(&mut self, position: Option<Position>) -> Result<(), io::Error> {
+        self.cursor = position;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+const QUOTES: &[char] = &['"', '\'', '`'];
+
+// The closing bracket for an opening one.
+pub fn closing(open: char) -> Option<char> {
+    PAIRS.iter().find(|&&(o, _)| o == open).map(|&(_, close)| close)
+}
+
+// The opening bracket for a closing one.
+pub fn opening(close: char) -> Option<char> {
+    PAIRS.iter().find(|&&(_, c)| c == close).map(|&(open, _)| open)
+}
+
+pub fn is_quote(c: char) -> bool {
+    QUOTES.contains(&c)
+}
+
+// What typing `c` should add after the cursor, if it starts a pair.
+pub fn pair_for(c: char) -> Option<char> {
+    closing(c).or_else(|| Some(c).filter(|&c| is_quote(c)))
+}
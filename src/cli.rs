@@ -0,0 +1,192 @@
+use encoding_rs::Encoding;
+use rustte::Position;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "\
+Usage: rustte [OPTIONS] [+LINE] [FILE[:LINE[:COL]]]...
+
+Opens each FILE in its own buffer; Alt-n and Alt-p switch between them.
+A FILE of - reads standard input into an unnamed buffer, as in
+`git log | rustte -`.
+
+Options:
+  +LINE             Start the next file at LINE
+  -R, --readonly    Open every file read-only (Alt-r toggles)
+  --config PATH     Read settings from PATH
+  --encoding NAME   Read and write files in encoding NAME (default UTF-8,
+                    or the charset from .editorconfig)
+  --stdout          Write the buffer to standard output on quit
+  -h, --help        Print this help and exit
+  -V, --version     Print the version and exit
+";
+
+pub struct File {
+    // "-" stands for standard input.
+    pub path: String,
+    pub position: Option<Position>,
+}
+
+pub struct Options {
+    pub files: Vec<File>,
+    pub read_only: bool,
+    pub config: Option<PathBuf>,
+    // None leaves it to .editorconfig files.
+    pub encoding: Option<&'static Encoding>,
+    pub stdout: bool,
+}
+
+pub enum Command {
+    Edit(Options),
+    Help,
+    Version,
+}
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut options = Options {
+        files: Vec::new(),
+        read_only: false,
+        config: None,
+        encoding: None,
+        stdout: false,
+    };
+    let mut line = None;
+    let mut only_files = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with(['-', '+']) {
+            let mut file = file(arg);
+            file.position = line.take().or(file.position);
+            if file.path == "-" && options.files.iter().any(|file| file.path == "-") {
+                return Err("standard input can only be read once".to_string());
+            }
+            options.files.push(file);
+            continue;
+        }
+        if let Some(number) = arg.strip_prefix('+') {
+            line = Some(Position {
+                x: 0,
+                y: parse_line(number).ok_or_else(|| format!("invalid line number: {}", arg))?,
+            });
+            continue;
+        }
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (&arg[..], None),
+        };
+        let mut value = || {
+            value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match name {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-R" | "--readonly" => options.read_only = true,
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--encoding" => options.encoding = Some(encoding(&value()?)?),
+            "--stdout" => options.stdout = true,
+            "--" => only_files = true,
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    // A trailing +LINE applies to the last file, as in `rustte notes.txt +12`.
+    if let (Some(line), Some(file)) = (line, options.files.last_mut()) {
+        file.position = Some(line);
+    }
+    Ok(Command::Edit(options))
+}
+
+// Lines and columns on the command line count from 1.
+fn parse_line(number: &str) -> Option<usize> {
+    number.parse::<usize>().ok()?.checked_sub(1)
+}
+
+// Splits `path:line` and `path:line:col`, as printed by compilers and grep,
+// unless a file by the full name exists.
+fn file(arg: String) -> File {
+    if arg == "-" || Path::new(&arg).exists() {
+        return File {
+            path: arg,
+            position: None,
+        };
+    }
+    let mut parts = arg.rsplitn(3, ':');
+    let last = parts.next().and_then(parse_line);
+    let middle = parts.next();
+    let position = match (parts.next(), middle.and_then(parse_line), last) {
+        (Some(path), Some(y), Some(x)) => Some((path, Position { x, y })),
+        _ => arg
+            .rsplit_once(':')
+            .and_then(|(path, line)| Some((path, Position { x: 0, y: parse_line(line)? }))),
+    };
+    match position {
+        Some((path, position)) if !path.is_empty() => File {
+            path: path.to_string(),
+            position: Some(position),
+        },
+        _ => File {
+            path: arg,
+            position: None,
+        },
+    }
+}
+
+fn encoding(label: &str) -> Result<&'static Encoding, String> {
+    let encoding =
+        Encoding::for_label(label.as_bytes()).ok_or_else(|| format!("unknown encoding: {}", label))?;
+    // UTF-16 and a few others can be read but not written back.
+    if encoding.output_encoding() != encoding {
+        return Err(format!("can't write files in {}", encoding.name()));
+    }
+    Ok(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edit(args: &[&str]) -> Options {
+        match parse(args.iter().map(|arg| arg.to_string())) {
+            Ok(Command::Edit(options)) => options,
+            _ => panic!("expected options for {:?}", args),
+        }
+    }
+
+    fn positions(options: &Options) -> Vec<(&str, Option<Position>)> {
+        options.files.iter().map(|file| (&file.path[..], file.position)).collect()
+    }
+
+    #[test]
+    fn positions_from_plus_line_and_suffixes() {
+        let options = edit(&["+3", "missing-a", "missing-b:7", "missing-c:2:5", "-"]);
+        assert_eq!(
+            positions(&options),
+            vec![
+                ("missing-a", Some(Position { x: 0, y: 2 })),
+                ("missing-b", Some(Position { x: 0, y: 6 })),
+                ("missing-c", Some(Position { x: 4, y: 1 })),
+                ("-", None),
+            ]
+        );
+        let options = edit(&["missing-a", "+12"]);
+        assert_eq!(positions(&options), vec![("missing-a", Some(Position { x: 0, y: 11 }))]);
+    }
+
+    #[test]
+    fn flags() {
+        let options = edit(&["-R", "--config=rc", "--encoding", "latin1", "--stdout", "--", "-x"]);
+        assert!(options.read_only);
+        assert!(options.stdout);
+        assert_eq!(options.config, Some(PathBuf::from("rc")));
+        assert_eq!(options.encoding.map(Encoding::name), Some("windows-1252"));
+        assert_eq!(positions(&options), vec![("-x", None)]);
+    }
+
+    #[test]
+    fn errors() {
+        for args in [&["--bogus"][..], &["+x", "a"], &["--config"], &["--encoding", "utf-16le"], &["-", "-"]] {
+            assert!(parse(args.iter().map(|arg| arg.to_string())).is_err(), "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::SaveOptions;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+use termion::color::Rgb;
+
+// Settings an embedding application can change before starting an editor.
+#[derive(Clone)]
+pub struct Config {
+    pub status_bg: Rgb,
+    pub status_fg: Rgb,
+    pub selection_bg: Rgb,
+    // Behind the bracket at the cursor and its match.
+    pub match_bg: Rgb,
+    // Behind whitespace at the end of rows, unless it is switched off.
+    pub trailing_whitespace_bg: Rgb,
+    pub show_trailing_whitespace: bool,
+    // Draw tabs, trailing spaces and invisible characters as dimmed glyphs
+    // in `whitespace_fg`; Alt-. toggles it.
+    pub show_whitespace: bool,
+    pub whitespace_fg: Rgb,
+    // Ctrl-q presses needed to quit with unsaved changes.
+    pub quit_times: u8,
+    // How long to wait for a key before doing idle work such as writing the
+    // swap file.
+    pub input_timeout: Duration,
+    pub swap_interval: Duration,
+    pub disk_check_interval: Duration,
+    // How long a status message stays on screen.
+    pub message_timeout: Duration,
+    // Open every document read-only, for looking at files without risk.
+    pub read_only: bool,
+    // Columns per indent level, and whether a level is a tab instead of
+    // that many spaces.
+    pub indent_width: usize,
+    pub indent_tabs: bool,
+    // Tabs reach to the next multiple of this many columns on screen.
+    pub tab_width: usize,
+    // Close brackets and quotes as they are opened.
+    pub auto_pairs: bool,
+    // Wrap long rows onto more screen lines instead of scrolling sideways,
+    // starting each continuation line with `wrap_marker`.
+    pub soft_wrap: bool,
+    pub wrap_marker: String,
+    // The column Alt-q refills paragraphs to, and that typing breaks lines
+    // at when `auto_wrap` is on.
+    pub wrap_column: usize,
+    pub auto_wrap: bool,
+    // What saving does to the text first; see `SaveOptions`.
+    pub trim_trailing_whitespace: bool,
+    pub trim_modified_only: bool,
+    pub final_newline: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            status_bg: Rgb(239, 239, 239),
+            status_fg: Rgb(63, 63, 63),
+            selection_bg: Rgb(68, 85, 119),
+            match_bg: Rgb(85, 85, 85),
+            trailing_whitespace_bg: Rgb(153, 51, 51),
+            show_trailing_whitespace: true,
+            show_whitespace: false,
+            whitespace_fg: Rgb(110, 110, 110),
+            quit_times: 3,
+            input_timeout: Duration::from_millis(500),
+            swap_interval: Duration::from_secs(2),
+            disk_check_interval: Duration::from_secs(2),
+            message_timeout: Duration::from_secs(5),
+            read_only: false,
+            indent_width: 4,
+            indent_tabs: false,
+            tab_width: 4,
+            auto_pairs: true,
+            soft_wrap: false,
+            wrap_marker: "↪ ".to_string(),
+            wrap_column: 72,
+            auto_wrap: false,
+            trim_trailing_whitespace: false,
+            trim_modified_only: false,
+            final_newline: false,
+        }
+    }
+}
+
+impl Config {
+    // Reads `key = value` lines over the defaults. Blank lines and lines
+    // starting with '#' are skipped.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read_to_string(path)?;
+        let mut config = Self::default();
+        for (number, line) in (1_usize..).zip(contents.lines()) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match line.split_once('=') {
+                Some((key, value)) => config.set(key.trim(), value.trim()),
+                None => Err("expected key = value".to_string()),
+            };
+            if let Err(message) = result {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{}:{}: {}", path.display(), number, message),
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "status_bg" => self.status_bg = parse_color(value)?,
+            "status_fg" => self.status_fg = parse_color(value)?,
+            "selection_bg" => self.selection_bg = parse_color(value)?,
+            "match_bg" => self.match_bg = parse_color(value)?,
+            "trailing_whitespace_bg" => self.trailing_whitespace_bg = parse_color(value)?,
+            "show_trailing_whitespace" => self.show_trailing_whitespace = parse_bool(value)?,
+            "show_whitespace" => self.show_whitespace = parse_bool(value)?,
+            "whitespace_fg" => self.whitespace_fg = parse_color(value)?,
+            "quit_times" => self.quit_times = parse_number(value)?,
+            "input_timeout_ms" => self.input_timeout = Duration::from_millis(parse_number(value)?),
+            "swap_interval_ms" => self.swap_interval = Duration::from_millis(parse_number(value)?),
+            "disk_check_interval_ms" => {
+                self.disk_check_interval = Duration::from_millis(parse_number(value)?);
+            }
+            "message_timeout_ms" => self.message_timeout = Duration::from_millis(parse_number(value)?),
+            "read_only" => self.read_only = parse_bool(value)?,
+            "indent_width" => self.indent_width = parse_number(value)?,
+            "indent_tabs" => self.indent_tabs = parse_bool(value)?,
+            "tab_width" => self.tab_width = parse_number(value)?,
+            "auto_pairs" => self.auto_pairs = parse_bool(value)?,
+            "soft_wrap" => self.soft_wrap = parse_bool(value)?,
+            "wrap_marker" => self.wrap_marker = value.to_string(),
+            "wrap_column" => self.wrap_column = parse_number(value)?,
+            "auto_wrap" => self.auto_wrap = parse_bool(value)?,
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = parse_bool(value)?,
+            "trim_modified_only" => self.trim_modified_only = parse_bool(value)?,
+            "final_newline" => self.final_newline = parse_bool(value)?,
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+
+    // The text of one indent level.
+    pub fn indent_unit(&self) -> String {
+        if self.indent_tabs {
+            "\t".to_string()
+        } else {
+            " ".repeat(self.indent_width)
+        }
+    }
+
+    pub fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+            trim_modified_only: self.trim_modified_only,
+            final_newline: self.final_newline,
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a valid number", value))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("{} is not true or false", value)),
+    }
+}
+
+// Colors are written as #rrggbb.
+fn parse_color(value: &str) -> Result<Rgb, String> {
+    let invalid = || format!("{} is not a #rrggbb color", value);
+    let hex = value.strip_prefix('#').filter(|hex| hex.len() == 6).ok_or_else(invalid)?;
+    let channel = |range| {
+        hex.get(range)
+            .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+            .ok_or_else(invalid)
+    };
+    Ok(Rgb(channel(0..2)?, channel(2..4)?, channel(4..6)?))
+}
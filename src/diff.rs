@@ -0,0 +1,153 @@
+use std::cmp;
+
+const CONTEXT: usize = 3;
+const MAX_TABLE: usize = 4_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// Line-based diff of `old` against `new`, rendered as unified-style hunks.
+pub fn unified<S: AsRef<str>, T: AsRef<str>>(old: &[S], new: &[T]) -> Vec<String> {
+    let ops = operations(old, new);
+    let mut result = Vec::new();
+    let mut old_idx: usize = 0;
+    let mut new_idx: usize = 0;
+    let mut idx: usize = 0;
+    while idx < ops.len() {
+        if ops.get(idx) == Some(&Op::Equal) {
+            idx = idx.saturating_add(1);
+            old_idx = old_idx.saturating_add(1);
+            new_idx = new_idx.saturating_add(1);
+            continue;
+        }
+        // Walk back over up to CONTEXT equal lines, then forward until the
+        // change run is followed by more than 2 * CONTEXT equal lines.
+        let lead = cmp::min(CONTEXT, cmp::min(old_idx, new_idx));
+        let mut start = idx.saturating_sub(lead);
+        let mut old_line = old_idx.saturating_sub(lead);
+        let mut new_line = new_idx.saturating_sub(lead);
+        let mut end = idx;
+        let mut equal_run: usize = 0;
+        while end < ops.len() {
+            if ops.get(end) == Some(&Op::Equal) {
+                equal_run = equal_run.saturating_add(1);
+                if equal_run > CONTEXT.saturating_mul(2) {
+                    equal_run = equal_run.saturating_sub(1);
+                    break;
+                }
+            } else {
+                equal_run = 0;
+            }
+            end = end.saturating_add(1);
+        }
+        end = end.saturating_sub(equal_run.saturating_sub(CONTEXT));
+        result.push(format!(
+            "@@ -{} +{} @@",
+            old_line.saturating_add(1),
+            new_line.saturating_add(1)
+        ));
+        while start < end {
+            match ops.get(start) {
+                Some(Op::Equal) => {
+                    let line = old.get(old_line).map_or("", AsRef::as_ref);
+                    result.push(format!("  {}", line));
+                    old_line = old_line.saturating_add(1);
+                    new_line = new_line.saturating_add(1);
+                }
+                Some(Op::Delete) => {
+                    let line = old.get(old_line).map_or("", AsRef::as_ref);
+                    result.push(format!("- {}", line));
+                    old_line = old_line.saturating_add(1);
+                }
+                Some(Op::Insert) => {
+                    let line = new.get(new_line).map_or("", AsRef::as_ref);
+                    result.push(format!("+ {}", line));
+                    new_line = new_line.saturating_add(1);
+                }
+                None => break,
+            }
+            start = start.saturating_add(1);
+        }
+        idx = end;
+        old_idx = old_line;
+        new_idx = new_line;
+    }
+    result
+}
+
+// Indices of the lines in `new` that aren't in `old`.
+pub fn changed_lines<S: AsRef<str>, T: AsRef<str>>(old: &[S], new: &[T]) -> Vec<usize> {
+    let mut result = Vec::new();
+    let mut new_idx: usize = 0;
+    for op in operations(old, new) {
+        match op {
+            Op::Equal => new_idx = new_idx.saturating_add(1),
+            Op::Insert => {
+                result.push(new_idx);
+                new_idx = new_idx.saturating_add(1);
+            }
+            Op::Delete => {}
+        }
+    }
+    result
+}
+
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+fn operations<S: AsRef<str>, T: AsRef<str>>(old: &[S], new: &[T]) -> Vec<Op> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a.as_ref() == b.as_ref())
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a.as_ref() == b.as_ref())
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    let mut ops = vec![Op::Equal; prefix];
+    if n.saturating_mul(m) > MAX_TABLE {
+        // Too large to align line by line; report a wholesale replacement.
+        ops.extend(std::iter::repeat(Op::Delete).take(n));
+        ops.extend(std::iter::repeat(Op::Insert).take(m));
+    } else {
+        // lcs[i][j] is the LCS length of old_mid[i..] and new_mid[j..].
+        let width = m + 1;
+        let mut lcs = vec![0_u32; (n + 1) * width];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * width + j] = if old_mid[i].as_ref() == new_mid[j].as_ref() {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    cmp::max(lcs[(i + 1) * width + j], lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if old_mid[i].as_ref() == new_mid[j].as_ref() {
+                ops.push(Op::Equal);
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                ops.push(Op::Delete);
+                i += 1;
+            } else {
+                ops.push(Op::Insert);
+                j += 1;
+            }
+        }
+        ops.extend(std::iter::repeat(Op::Delete).take(n - i));
+        ops.extend(std::iter::repeat(Op::Insert).take(m - j));
+    }
+    ops.extend(std::iter::repeat(Op::Equal).take(suffix));
+    ops
+}
@@ -1,29 +1,364 @@
+use crate::brackets;
+use crate::filetype::FileType;
+use crate::history::{Edit, History};
+use crate::reflow;
+use crate::indent;
+use crate::mapped::Indexer;
+use crate::rope::{Rope, Segment};
 use crate::Position;
 use crate::Row;
+use crate::Swap;
+use encoding_rs::{Encoding, UTF_8};
+use unicode_segmentation::UnicodeSegmentation;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::CString;
 use std::fs;
-use std::io::{Error, Write};
+use std::hash::Hasher;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Files at least this big are memory-mapped and indexed in the background.
+const LARGE_FILE: u64 = 16 * 1024 * 1024;
+// How far to look for a matching bracket, so a stray one in a huge file
+// doesn't stall every redraw.
+const MATCH_ROWS: usize = 10_000;
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+// How much of a mapped file to look through for the end of the first line.
+const HEAD: usize = 64 * 1024;
+
+// What the file looked like on disk the last time we read or wrote it.
+#[derive(PartialEq)]
+struct DiskState {
+    modified: SystemTime,
+    len: u64,
+    hash: u64,
+}
+
+impl DiskState {
+    fn new(filename: &str, hash: u64) -> Result<Self, Error> {
+        let metadata = fs::metadata(filename)?;
+        Ok(Self {
+            modified: metadata.modified()?,
+            len: metadata.len(),
+            hash,
+        })
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+// A UTF-8 byte order mark isn't part of the text; `Document::bom` says
+// whether to write it back.
+fn decode(bytes: &[u8], encoding: &'static Encoding) -> Result<String, Error> {
+    if encoding == UTF_8 {
+        let bytes = bytes.strip_prefix(BOM).unwrap_or(bytes);
+        return String::from_utf8(bytes.to_vec()).map_err(|error| Error::new(ErrorKind::InvalidData, error));
+    }
+    Ok(encoding.decode_without_bom_handling(bytes).0.into_owned())
+}
+
+// Files we couldn't write to, either by their mode or for lack of access,
+// open read-only.
+fn writable(filename: &str) -> bool {
+    let read_only = fs::metadata(filename).map_or(false, |metadata| metadata.permissions().readonly());
+    let path = match CString::new(filename) {
+        Ok(path) => path,
+        Err(_) => return !read_only,
+    };
+    // SAFETY: access only reads the NUL-terminated path it is given.
+    !read_only && unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0
+}
+
+// What `load` found in a file.
+struct Loaded {
+    rows: Rope,
+    indexer: Option<Indexer>,
+    disk: Option<DiskState>,
+    line_ending: LineEnding,
+    bom: bool,
+}
+
+fn load(filename: &str, encoding: &'static Encoding) -> Result<Loaded, Error> {
+    let mut file = fs::File::open(filename)?;
+    // Only UTF-8 lines can be used straight from the map.
+    if encoding == UTF_8 && file.metadata()?.len() >= LARGE_FILE {
+        let indexer = Indexer::start(&file)?;
+        let head = indexer.bytes().get(..HEAD).unwrap_or_else(|| indexer.bytes());
+        let line_ending = LineEnding::of(head);
+        // The index only breaks lines at \n, so CR-only files are read whole.
+        // The hash is filled in once the indexer has seen the whole file. A
+        // byte order mark stays in the first row and is written back with it.
+        if line_ending != LineEnding::Cr {
+            return Ok(Loaded {
+                rows: Rope::default(),
+                line_ending,
+                indexer: Some(indexer),
+                disk: DiskState::new(filename, 0).ok(),
+                bom: false,
+            });
+        }
+    }
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let line_ending = LineEnding::of(&bytes);
+    Ok(Loaded {
+        rows: line_ending.lines(&decode(&bytes, encoding)?).map(Row::from).collect(),
+        indexer: None,
+        disk: DiskState::new(filename, hash_bytes(&bytes)).ok(),
+        line_ending,
+        bom: encoding == UTF_8 && bytes.starts_with(BOM),
+    })
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct SortOptions {
+    pub ignore_case: bool,
+    // Order by the number each line starts with.
+    pub numeric: bool,
+    // Keep only the first of lines that compare equal.
+    pub unique: bool,
+}
+
+impl SortOptions {
+    fn compare(self, a: &str, b: &str) -> cmp::Ordering {
+        if self.numeric {
+            let number = |line: &str| {
+                let line = line.trim_start();
+                let end = line
+                    .char_indices()
+                    .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && c == '-')))
+                    .map_or(line.len(), |(i, _)| i);
+                line.get(..end).and_then(|number| number.parse::<f64>().ok())
+            };
+            // Lines without a number go first, as with sort -n.
+            let order = match (number(a), number(b)) {
+                (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(cmp::Ordering::Equal),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            };
+            if order != cmp::Ordering::Equal || self.unique {
+                return order;
+            }
+        }
+        if self.ignore_case {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        } else {
+            a.cmp(b)
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    // The ending of the first line in `bytes`. Only text without a single
+    // \n counts as ending its lines with a bare \r.
+    fn of(bytes: &[u8]) -> Self {
+        match memchr::memchr(b'\n', bytes) {
+            Some(end) if end > 0 && bytes.get(end.saturating_sub(1)) == Some(&b'\r') => Self::CrLf,
+            None if memchr::memchr(b'\r', bytes).is_some() => Self::Cr,
+            _ => Self::Lf,
+        }
+    }
+
+    // The lines of `text`, which `str::lines` only splits at \n.
+    fn lines(self, text: &str) -> Box<dyn Iterator<Item = &str> + '_> {
+        match self {
+            Self::Cr if !text.is_empty() => Box::new(text.strip_suffix('\r').unwrap_or(text).split('\r')),
+            _ => Box::new(text.lines()),
+        }
+    }
+
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Self::Lf => b"\n",
+            Self::CrLf => b"\r\n",
+            Self::Cr => b"\r",
+        }
+    }
+}
+
+// How the text is tidied up before it is saved.
+#[derive(Default, Clone, Copy)]
+pub struct SaveOptions {
+    pub trim_trailing_whitespace: bool,
+    // Only trim rows that differ from the file on disk, so untouched lines
+    // don't show up in diffs.
+    pub trim_modified_only: bool,
+    // Drop empty rows at the end of the file.
+    pub final_newline: bool,
+}
 
 #[derive(Default)]
 pub struct Document {
-        rows: Vec<Row>,
+        rows: Rope,
         pub name: Option<String>,
         dirty: bool, 
+        swap_dirty: bool,
+        swap: Option<Swap>,
+        // Which of the file's swap paths is ours: the one the swap file
+        // found on opening was at, or a free one beside it once the user
+        // keeps that file for later.
+        swap_number: usize,
+        disk: Option<DiskState>,
+        history: History,
+        indexer: Option<Indexer>,
+        // Rows may still point into the memory-mapped file.
+        mapped: bool,
+        // None means UTF-8.
+        encoding: Option<&'static Encoding>,
+        read_only: bool,
+        // What rows end with when saved, taken from the first line read.
+        line_ending: LineEnding,
+        // Whether the file starts with a UTF-8 byte order mark.
+        bom: bool,
+        // Leave the line ending off the last row when saving.
+        omit_final_newline: bool,
 }
 
 impl Document {
     pub fn open(filename: &str) -> Result<Self, std::io::Error> {
-        let contents = fs::read_to_string(filename)?;
-        let mut rows = Vec::new();
-        for value in contents.lines() {
-            rows.push(Row::from(value));
-        }
+        Self::open_with_encoding(filename, UTF_8)
+    }
+
+    pub fn open_with_encoding(filename: &str, encoding: &'static Encoding) -> Result<Self, std::io::Error> {
+        let loaded = load(filename, encoding)?;
+        let (swap_number, swap) = Swap::find(filename);
         Ok(Self { 
-            rows,
+            rows: loaded.rows,
             name: Some(filename.to_string()),
             dirty: false,
+            swap_dirty: false,
+            swap,
+            swap_number,
+            disk: loaded.disk,
+            history: History::default(),
+            mapped: loaded.indexer.is_some(),
+            indexer: loaded.indexer,
+            encoding: Some(encoding),
+            read_only: !writable(filename),
+            line_ending: loaded.line_ending,
+            bom: loaded.bom,
+            omit_final_newline: false,
+        })
+    }
+
+    // An empty document for a file that doesn't exist yet; saving creates it.
+    pub fn new_file(filename: &str, encoding: &'static Encoding) -> Self {
+        let (swap_number, swap) = Swap::find(filename);
+        Self {
+            name: Some(filename.to_string()),
+            swap,
+            swap_number,
+            encoding: Some(encoding),
+            ..Self::default()
+        }
+    }
+
+    // An unnamed document holding everything `reader` produces.
+    pub fn from_reader<R: Read>(mut reader: R, encoding: &'static Encoding) -> Result<Self, std::io::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let line_ending = LineEnding::of(&bytes);
+        Ok(Self {
+            rows: line_ending.lines(&decode(&bytes, encoding)?).map(Row::from).collect(),
+            encoding: Some(encoding),
+            line_ending,
+            bom: encoding == UTF_8 && bytes.starts_with(BOM),
+            ..Self::default()
         })
     }
 
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding.unwrap_or(UTF_8)
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::from_name(self.name.as_deref())
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    pub fn set_bom(&mut self, bom: bool) {
+        self.bom = bom;
+    }
+
+    pub fn set_final_newline(&mut self, final_newline: bool) {
+        self.omit_final_newline = !final_newline;
+    }
+
+    // Adds the rows indexed in the background since the last call. Returns
+    // whether anything changed.
+    pub fn poll_index(&mut self) -> bool {
+        let indexer = if let Some(indexer) = &mut self.indexer {
+            indexer
+        } else {
+            return false;
+        };
+        let (chunks, hash) = indexer.poll();
+        let changed = !chunks.is_empty();
+        for chunk in chunks {
+            self.rows.push_chunk(chunk);
+        }
+        if let Some(hash) = hash {
+            self.indexer = None;
+            if let Some(disk) = &mut self.disk {
+                disk.hash = hash;
+            }
+            return true;
+        }
+        changed
+    }
+
+    pub fn indexing_progress(&self) -> Option<u8> {
+        self.indexer.as_ref().map(Indexer::percent)
+    }
+
+    // Edits are refused while indexing and in read-only documents.
+    fn is_locked(&self) -> bool {
+        self.is_indexing() || self.read_only
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        self.indexer.is_some()
+    }
+
+    pub fn from_lines(lines: &[String]) -> Self {
+        Self {
+            rows: lines.iter().map(|line| Row::from(&line[..])).collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        self.rows.iter().map(Row::as_str).collect()
+    }
+
     pub fn row(&self, index: usize) -> Option<&Row> {
         self.rows.get(index)
     }
@@ -36,65 +371,718 @@ impl Document {
         self.rows.len()
     }
 
-    fn insert_newline(&mut self, at: &Position) {
+    pub fn insert(&mut self, at: &Position, c: char) {
         if at.y > self.rows.len() {
             return;
         }
-        if at.y == self.rows.len() {
-            self.rows.push(Row::default());
+        self.replace(at, at, &c.to_string());
+    }
+
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    pub fn delete(&mut self, at: &Position) {
+        let len = self.rows.len();
+        if at.y >= len {
             return;
         }
-        #[allow(clippy::indexing_slicing)]
-        let new_row = self.rows[at.y].split(at.x);
-        #[allow(clippy::integer_arithmetic)]
-        self.rows.insert(at.y + 1, new_row);
+        let end = if at.x < self.rows.get(at.y).map_or(0, Row::len) {
+            Position { x: at.x + 1, y: at.y }
+        } else if at.y + 1 < len {
+            Position { x: 0, y: at.y + 1 }
+        } else {
+            return;
+        };
+        self.replace(at, &end, "");
     }
 
-    pub fn insert(&mut self, at: &Position, c: char) {
-        if at.y > self.rows.len() {
+    // Replaces the text between two positions as one undo step and returns
+    // the position right after the inserted text.
+    pub fn replace(&mut self, start: &Position, end: &Position, text: &str) -> Position {
+        if self.is_locked() {
+            return *start;
+        }
+        let (start, end) = self.clamp_range(start, end);
+        let removed = self.remove_text(&start, &end);
+        let mut inserted = text.to_string();
+        // Text typed past the last row starts a row of its own, which its
+        // undo has to take away again.
+        if start.y == self.rows.len() && !inserted.is_empty() && !inserted.ends_with('\n') {
+            inserted.push('\n');
+        }
+        self.insert_text(&start, &inserted);
+        if !removed.is_empty() || !inserted.is_empty() {
+            self.history.record(Edit {
+                at: start,
+                removed,
+                inserted,
+            });
+            self.changed();
+        }
+        crate::history::end_of(start, text)
+    }
+
+    // Applies `edit` to every row from `first` to `last` as one undo step.
+    // It returns the columns to replace in that row and what goes there.
+    fn edit_lines<F>(&mut self, first: usize, last: usize, mut edit: F)
+    where
+        F: FnMut(&str) -> Option<(usize, usize, String)>,
+    {
+        if self.is_locked() {
             return;
         }
-        self.dirty = true;
-        if c == '\n' {
-            self.insert_newline(at);
+        self.begin_undo_group();
+        for y in first..=cmp::min(last, self.len().saturating_sub(1)) {
+            if let Some((start, end, text)) = self.rows.get(y).and_then(|row| edit(row.as_str())) {
+                self.replace(&Position { x: start, y }, &Position { x: end, y }, &text);
+            }
+        }
+        self.end_undo_group();
+    }
+
+    // Puts `unit` in front of every non-empty row from `first` to `last`.
+    pub fn indent_lines(&mut self, first: usize, last: usize, unit: &str) {
+        self.edit_lines(first, last, |line| {
+            (!line.is_empty()).then(|| (0, 0, unit.to_string()))
+        });
+    }
+
+    // Takes one indent level off every row from `first` to `last`.
+    pub fn outdent_lines(&mut self, first: usize, last: usize, width: usize) {
+        self.edit_lines(first, last, |line| {
+            let leading = indent::leading(line);
+            let kept = indent::outdent(leading, width).chars().count();
+            let len = leading.chars().count();
+            (kept < len).then(|| (kept, len, String::new()))
+        });
+    }
+
+    // Comments out the rows from `first` to `last` with `token`, lined up
+    // with the least indented one, or uncomments them if they all are
+    // already. Blank rows are left alone.
+    pub fn toggle_comment(&mut self, first: usize, last: usize, token: &str) {
+        let lines: Vec<&str> = self
+            .rows
+            .iter_at(first)
+            .take(last.saturating_sub(first).saturating_add(1))
+            .map(Row::as_str)
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        let commented = !lines.is_empty() && lines.iter().all(|line| line.trim_start().starts_with(token));
+        let column = lines
+            .iter()
+            .map(|line| indent::leading(line).chars().count())
+            .min()
+            .unwrap_or(0);
+        let token_len = token.chars().count();
+        self.edit_lines(first, last, |line| {
+            if line.trim().is_empty() {
+                return None;
+            }
+            let start = indent::leading(line).chars().count();
+            if commented {
+                let rest = line.trim_start().get(token.len()..).unwrap_or("");
+                let space = usize::from(rest.starts_with(' '));
+                Some((start, start.saturating_add(token_len).saturating_add(space), String::new()))
+            } else {
+                Some((column, column, format!("{} ", token)))
+            }
+        });
+    }
+
+    // Replaces the rows from `first` to `last` with what `edit` makes of
+    // them, as one undo step. Returns false if there was nothing to change.
+    fn replace_lines<F>(&mut self, first: usize, last: usize, edit: F) -> bool
+    where
+        F: FnOnce(Vec<String>) -> Vec<String>,
+    {
+        if self.is_locked() || first > last || last >= self.len() {
+            return false;
+        }
+        let lines: Vec<String> = self
+            .rows
+            .iter_at(first)
+            .take(last.saturating_sub(first).saturating_add(1))
+            .map(|row| row.as_str().to_string())
+            .collect();
+        let edited = edit(lines.clone());
+        if edited == lines {
+            return false;
+        }
+        let end = Position {
+            x: self.rows.get(last).map_or(0, Row::len),
+            y: last,
+        };
+        self.replace(&Position { x: 0, y: first }, &end, &edited.join("\n"));
+        true
+    }
+
+    // Swaps the rows from `first` to `last` with the row above or below
+    // them. Returns false at the top or bottom of the document.
+    pub fn move_lines(&mut self, first: usize, last: usize, up: bool) -> bool {
+        if up {
+            first > 0 && self.replace_lines(first.saturating_sub(1), last, |mut lines| {
+                lines.rotate_left(1);
+                lines
+            })
+        } else {
+            self.replace_lines(first, last.saturating_add(1), |mut lines| {
+                lines.rotate_right(1);
+                lines
+            })
+        }
+    }
+
+    // Puts a copy of the rows from `first` to `last` right below them.
+    pub fn duplicate_lines(&mut self, first: usize, last: usize) -> bool {
+        self.replace_lines(first, last, |lines| [lines.clone(), lines].concat())
+    }
+
+    pub fn delete_lines(&mut self, first: usize, last: usize) {
+        let len = self.len();
+        if first > last || last >= len {
             return;
         }
-        if at.y == self.rows.len() {
-            let mut row = Row::default();
-            row.insert(0, c);
-            self.rows.push(row);
+        let row_end = |y: usize| Position {
+            x: self.rows.get(y).map_or(0, Row::len),
+            y,
+        };
+        // Take the line break after the rows, or before them for the last
+        // rows of the document.
+        let (start, end) = if last.saturating_add(1) < len {
+            (Position { x: 0, y: first }, Position { x: 0, y: last.saturating_add(1) })
+        } else if first > 0 {
+            (row_end(first.saturating_sub(1)), row_end(last))
         } else {
-            #[allow(clippy::indexing_slicing)]
-            let row = &mut self.rows[at.y];
-            row.insert(at.x, c);
+            (Position::default(), row_end(last))
+        };
+        self.replace(&start, &end, "");
+    }
+
+    // Joins the rows from `first` to `last`, or `first` and the next row,
+    // with single spaces in place of the indentation.
+    pub fn join_lines(&mut self, first: usize, last: usize) -> bool {
+        let last = if first == last { last.saturating_add(1) } else { last };
+        self.replace_lines(first, last, |lines| {
+            let mut joined = String::new();
+            for (n, line) in lines.iter().enumerate() {
+                let line = if n == 0 { line.trim_end() } else { line.trim() };
+                if !joined.trim().is_empty() && !line.is_empty() {
+                    joined.push(' ');
+                }
+                joined.push_str(line);
+            }
+            vec![joined]
+        })
+    }
+
+    pub fn sort_lines(&mut self, first: usize, last: usize, options: SortOptions) -> bool {
+        self.replace_lines(first, last, |mut lines| {
+            lines.sort_by(|a, b| options.compare(a, b));
+            if options.unique {
+                lines.dedup_by(|a, b| options.compare(a, b) == cmp::Ordering::Equal);
+            }
+            lines
+        })
+    }
+
+    pub fn reverse_lines(&mut self, first: usize, last: usize) -> bool {
+        self.replace_lines(first, last, |mut lines| {
+            lines.reverse();
+            lines
+        })
+    }
+
+    // The bracket pairing with the one at `at`, looked for at most
+    // MATCH_ROWS rows away.
+    pub fn matching_bracket(&self, at: &Position) -> Option<Position> {
+        let c = self.rows.get(at.y)?.slice(at.x, at.x.saturating_add(1)).chars().next()?;
+        let (open, close, forward) = match (brackets::closing(c), brackets::opening(c)) {
+            (Some(close), _) => (c, close, true),
+            (_, Some(open)) => (open, c, false),
+            _ => return None,
+        };
+        let (nested, unnested) = if forward { (open, close) } else { (close, open) };
+        let mut depth = 0_usize;
+        let mut visit = |x: usize, y: usize, grapheme: &str| {
+            let c = grapheme.chars().next();
+            if c == Some(nested) {
+                depth = depth.saturating_add(1);
+            } else if c == Some(unnested) {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return Some(Position { x, y });
+                }
+            }
+            None
+        };
+        if forward {
+            for (y, row) in (at.y..).zip(self.rows.iter_at(at.y)).take(MATCH_ROWS) {
+                let from = if y == at.y { at.x } else { 0 };
+                for x in from..row.len() {
+                    if let Some(position) = visit(x, y, row.slice(x, x.saturating_add(1))) {
+                        return Some(position);
+                    }
+                }
+            }
+        } else {
+            for y in (at.y.saturating_sub(MATCH_ROWS)..=at.y).rev() {
+                let row = self.rows.get(y)?;
+                let to = if y == at.y { at.x.saturating_add(1) } else { row.len() };
+                for x in (0..to).rev() {
+                    if let Some(position) = visit(x, y, row.slice(x, x.saturating_add(1))) {
+                        return Some(position);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // The rows around `y` that make up its paragraph: the ones that aren't
+    // blank and start with the same comment or quote markers.
+    pub fn paragraph(&self, y: usize) -> Option<(usize, usize)> {
+        let markers = |y: usize| {
+            let line = self.rows.get(y)?.as_str();
+            (!reflow::is_blank(line)).then(|| reflow::prefix(line).trim().to_string())
+        };
+        let own = markers(y)?;
+        let same = |y: usize| markers(y).as_ref() == Some(&own);
+        let mut first = y;
+        while first > 0 && same(first.saturating_sub(1)) {
+            first = first.saturating_sub(1);
+        }
+        let mut last = y;
+        while same(last.saturating_add(1)) {
+            last = last.saturating_add(1);
+        }
+        Some((first, last))
+    }
+
+    // Refills the paragraphs in the rows from `first` to `last` to `width`
+    // columns.
+    pub fn reflow_lines(&mut self, first: usize, last: usize, width: usize) -> bool {
+        self.replace_lines(first, last, |lines| reflow::reflow(&lines, width))
+    }
+
+    // Breaks row `y` at the last space before column `width` if it runs
+    // past it, continuing on a new row with the same prefix. Returns where
+    // the text after the break was, and where it is on the new row.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn wrap_row(&mut self, y: usize, width: usize) -> Option<(usize, usize)> {
+        let row = self.rows.get(y)?;
+        if row.len() <= width || self.is_locked() {
+            return None;
+        }
+        let is_space = |x: usize| row.slice(x, x + 1).chars().all(char::is_whitespace);
+        let prefix = reflow::prefix(row.as_str());
+        let prefix_len = prefix.graphemes(true).count();
+        let space = (prefix_len + 1..=width).rev().find(|&x| is_space(x))?;
+        let mut start = space;
+        while start > prefix_len && is_space(start - 1) {
+            start -= 1;
+        }
+        let mut end = space + 1;
+        while end < row.len() && is_space(end) {
+            end += 1;
         }
+        let continuation = reflow::continuation(prefix);
+        let continued = continuation.graphemes(true).count();
+        self.replace(
+            &Position { x: start, y },
+            &Position { x: end, y },
+            &format!("\n{}", continuation),
+        );
+        Some((end, continued))
     }
 
+    pub fn text(&self, start: &Position, end: &Position) -> String {
+        let mut result = String::new();
+        let rows = self.rows.iter_at(start.y).take(end.y.saturating_sub(start.y).saturating_add(1));
+        for (y, row) in (start.y..).zip(rows) {
+            let from = if y == start.y { start.x } else { 0 };
+            if y == end.y {
+                result.push_str(row.slice(from, end.x));
+            } else {
+                result.push_str(row.slice(from, row.len()));
+                result.push('\n');
+            }
+        }
+        result
+    }
+
+    pub fn begin_undo_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.history.end_group();
+    }
+
+    pub fn undo(&mut self) -> Option<Position> {
+        if self.is_locked() {
+            return None;
+        }
+        let group = self.history.take_undo()?;
+        let mut cursor = None;
+        for edit in group.iter().rev() {
+            self.remove_text(&edit.at, &edit.inserted_end());
+            self.insert_text(&edit.at, &edit.removed);
+            cursor = Some(edit.removed_end());
+        }
+        self.history.push_redo(group);
+        self.changed();
+        cursor
+    }
+
+    pub fn redo(&mut self) -> Option<Position> {
+        if self.is_locked() {
+            return None;
+        }
+        let group = self.history.take_redo()?;
+        let mut cursor = None;
+        for edit in &group {
+            self.remove_text(&edit.at, &edit.removed_end());
+            self.insert_text(&edit.at, &edit.inserted);
+            cursor = Some(edit.inserted_end());
+        }
+        self.history.push_undo(group);
+        self.changed();
+        cursor
+    }
+
+    fn changed(&mut self) {
+        self.dirty = !self.history.is_clean();
+        self.swap_dirty = true;
+    }
+
+    // Orders the positions and keeps them inside the document. A range may
+    // only end past the last row if it starts at the beginning of a row.
     #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
-    pub fn delete(&mut self, at: &Position) {
+    fn clamp_range(&self, start: &Position, end: &Position) -> (Position, Position) {
         let len = self.rows.len();
-        if at.y >= len {
+        let clamp = |at: &Position| {
+            let y = cmp::min(at.y, len);
+            let x = self.rows.get(y).map_or(0, |row| cmp::min(at.x, row.len()));
+            Position { x, y }
+        };
+        let (mut start, mut end) = (clamp(start), clamp(end));
+        if (end.y, end.x) < (start.y, start.x) {
+            std::mem::swap(&mut start, &mut end);
+        }
+        if end.y == len && start.y < len && start.x > 0 {
+            end = Position {
+                x: self.rows.get(len - 1).map_or(0, Row::len),
+                y: len - 1,
+            };
+        }
+        (start, end)
+    }
+
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    fn insert_text(&mut self, at: &Position, text: &str) {
+        if text.is_empty() || at.y > self.rows.len() {
             return;
         }
-        self.dirty = true;
-        if at.x == self.rows[at.y].len() && at.y + 1 < len {
-            let next_row = self.rows.remove(at.y + 1);
-            let row = &mut self.rows[at.y];
-            row.append(&next_row);
+        let mut text = text;
+        if at.y == self.rows.len() {
+            self.rows.push(Row::default());
+            text = text.strip_suffix('\n').unwrap_or(text);
+        }
+        let mut lines = text.split('\n');
+        let first = lines.next().unwrap_or("");
+        let mut new_rows: Vec<Row> = lines.map(Row::from).collect();
+        let row = if let Some(row) = self.rows.get_mut(at.y) {
+            row
+        } else {
+            return;
+        };
+        if let Some(last) = new_rows.last_mut() {
+            let tail = row.split(at.x);
+            row.append(&Row::from(first));
+            last.append(&tail);
+            self.rows.insert_rows(at.y + 1, new_rows);
+        } else {
+            row.insert_str(at.x, first);
+        }
+    }
+
+    #[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+    fn remove_text(&mut self, start: &Position, end: &Position) -> String {
+        if start == end {
+            return String::new();
+        }
+        let removed = self.text(start, end);
+        let len = self.rows.len();
+        if end.y >= len {
+            self.rows.remove_range(start.y..len);
+        } else if let Some(tail) = self.rows.get_mut(end.y).map(|row| row.split(end.x)) {
+            self.rows.remove_range(start.y + 1..end.y + 1);
+            if let Some(row) = self.rows.get_mut(start.y) {
+                row.split(start.x);
+                row.append(&tail);
+            }
+        }
+        removed
+    }
+
+    fn line_bytes(&self) -> impl Iterator<Item = &[u8]> {
+        self.rows
+            .segments()
+            .flat_map(|segment| -> Box<dyn Iterator<Item = &[u8]> + '_> {
+                match segment {
+                    Segment::Rows(rows) => Box::new(rows.iter().map(Row::as_bytes)),
+                    Segment::Lines(chunk) => Box::new(chunk.lines()),
+                }
+            })
+    }
+
+    // The text in the document's encoding, or None if that is UTF-8 and the
+    // rows can be written as they are. Fails before anything is written if
+    // some character has no representation in the encoding.
+    fn encoded(&self) -> Result<Option<Vec<u8>>, Error> {
+        let encoding = self.encoding();
+        if encoding == UTF_8 {
+            return Ok(None);
+        }
+        let mut text = String::new();
+        let ending = String::from_utf8_lossy(self.line_ending.as_bytes());
+        for row in self.rows.iter() {
+            text.push_str(row.as_str());
+            text.push_str(&ending);
+        }
+        if self.omit_final_newline {
+            text.truncate(text.len().saturating_sub(ending.len()));
+        }
+        let (bytes, _, unmappable) = encoding.encode(&text);
+        if unmappable {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("text can't be encoded as {}", encoding.name()),
+            ));
+        }
+        Ok(Some(bytes.into_owned()))
+    }
+
+    // Writes the text as it would be saved and returns its hash.
+    fn write_encoded<W: Write>(&self, encoded: Option<Vec<u8>>, writer: &mut W) -> Result<u64, Error> {
+        let mut hasher = DefaultHasher::new();
+        if let Some(bytes) = encoded {
+            writer.write_all(&bytes)?;
+            hasher.write(&bytes);
         } else {
-            let row = &mut self.rows[at.y];
-            row.delete(at.x);
+            if self.bom {
+                writer.write_all(BOM)?;
+                hasher.write(BOM);
+            }
+            let ending = self.line_ending.as_bytes();
+            let mut lines = self.line_bytes().peekable();
+            while let Some(line) = lines.next() {
+                writer.write_all(line)?;
+                hasher.write(line);
+                if lines.peek().is_some() || !self.omit_final_newline {
+                    writer.write_all(ending)?;
+                    hasher.write(ending);
+                }
+            }
+        }
+        Ok(hasher.finish())
+    }
+
+    // Writes the whole document somewhere other than its file, such as
+    // standard output.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        if self.is_indexing() {
+            return Err(Error::new(ErrorKind::Other, "file is still being indexed"));
+        }
+        let encoded = self.encoded()?;
+        self.write_encoded(encoded, writer)?;
+        writer.flush()
+    }
+
+    // Applies `options` to the text as one undo step, right before saving.
+    pub fn tidy(&mut self, options: SaveOptions) {
+        if self.is_locked() {
+            return;
+        }
+        self.begin_undo_group();
+        if options.trim_trailing_whitespace {
+            let rows = if options.trim_modified_only {
+                self.modified_rows()
+            } else {
+                (0..self.len()).collect()
+            };
+            for y in rows {
+                let (kept, len) = match self.rows.get(y) {
+                    Some(row) => (row.trailing_whitespace(), row.len()),
+                    None => continue,
+                };
+                if kept < len {
+                    self.replace(&Position { x: kept, y }, &Position { x: len, y }, "");
+                }
+            }
+        }
+        if options.final_newline {
+            let last = self.len().saturating_sub(1);
+            let keep = (0..=last)
+                .rev()
+                .find(|&y| self.rows.get(y).map_or(false, |row| !row.is_empty()))
+                .unwrap_or(0);
+            if keep < last {
+                let start = Position {
+                    x: self.rows.get(keep).map_or(0, Row::len),
+                    y: keep,
+                };
+                self.replace(&start, &Position { x: 0, y: last }, "");
+            }
+        }
+        self.end_undo_group();
+    }
+
+    // The rows that aren't in the file on disk; all of them if it can't be
+    // read.
+    fn modified_rows(&self) -> Vec<usize> {
+        match self.disk_lines() {
+            Ok(disk) => crate::diff::changed_lines(&disk, &self.lines()),
+            Err(_) => (0..self.len()).collect(),
         }
     }
 
     pub fn save(&mut self) -> Result<(), Error> {
         if let Some(filename) = &self.name {
-            let mut file = fs::File::create(filename)?;
-            for row in &self.rows {
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+            // Truncating a file that is still mapped would pull the rows out
+            // from under us, so write a new file and move it into place.
+            let path = if self.mapped {
+                let path = Path::new(filename);
+                let name = path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                path.with_file_name(format!(".{}.rustte.tmp", name))
+            } else {
+                Path::new(filename).to_path_buf()
+            };
+            let encoded = self.encoded()?;
+            let mut file = fs::File::create(&path)?;
+            let hash = self.write_encoded(encoded, &mut file)?;
+            drop(file);
+            if self.mapped {
+                if let Ok(metadata) = fs::metadata(filename) {
+                    fs::set_permissions(&path, metadata.permissions())?;
+                }
+                fs::rename(&path, filename)?;
             }
+            self.disk = DiskState::new(filename, hash).ok();
+            self.history.mark_clean();
             self.dirty = false;
+            self.swap_dirty = false;
+            self.remove_swap()?;
+        }
+        Ok(())
+    }
+
+    // Whether the file was changed by someone else since we last read or
+    // wrote it. A touched file with identical contents is not a change.
+    pub fn changed_on_disk(&mut self) -> Result<bool, Error> {
+        let (filename, disk) = match (&self.name, &mut self.disk) {
+            (Some(filename), Some(disk)) => (filename, disk),
+            _ => return Ok(false),
+        };
+        let metadata = match fs::metadata(filename) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(error) => return Err(error),
+        };
+        if self.indexer.is_some() {
+            return Ok(false);
+        }
+        let modified = metadata.modified()?;
+        if modified == disk.modified && metadata.len() == disk.len {
+            return Ok(false);
+        }
+        let hash = hash_bytes(&fs::read(filename)?);
+        if hash != disk.hash {
+            return Ok(true);
+        }
+        disk.modified = modified;
+        disk.len = metadata.len();
+        Ok(false)
+    }
+
+    // Accept the current file on disk as the base we're editing against,
+    // so the change is not reported again and a later save overwrites it.
+    pub fn ignore_disk_changes(&mut self) -> Result<(), Error> {
+        if let Some(filename) = &self.name {
+            let contents = fs::read(filename)?;
+            self.disk = DiskState::new(filename, hash_bytes(&contents)).ok();
+        }
+        Ok(())
+    }
+
+    pub fn reload(&mut self) -> Result<(), Error> {
+        if let Some(filename) = &self.name {
+            let loaded = load(filename, self.encoding())?;
+            self.rows = loaded.rows;
+            self.disk = loaded.disk;
+            self.mapped = loaded.indexer.is_some();
+            self.indexer = loaded.indexer;
+            self.line_ending = loaded.line_ending;
+            self.bom = loaded.bom;
+            self.history = History::default();
+            self.dirty = false;
+            self.swap_dirty = false;
+            self.remove_swap()?;
+        }
+        Ok(())
+    }
+
+    pub fn disk_lines(&self) -> Result<Vec<String>, Error> {
+        if let Some(filename) = &self.name {
+            let bytes = fs::read(filename)?;
+            let contents = decode(&bytes, self.encoding())?;
+            return Ok(LineEnding::of(&bytes).lines(&contents).map(String::from).collect());
+        }
+        Ok(Vec::new())
+    }
+
+    pub fn take_swap(&mut self) -> Option<Swap> {
+        self.swap.take()
+    }
+
+    // Leaves the swap file found on opening alone, writing ours beside it.
+    pub fn keep_swap(&mut self) {
+        if let Some(filename) = &self.name {
+            self.swap_number = Swap::free_number(filename);
+            self.swap_dirty = true;
+        }
+    }
+
+    fn swap_path(&self) -> Option<PathBuf> {
+        let filename = self.name.as_deref()?;
+        Some(Swap::numbered_path_for(filename, self.swap_number))
+    }
+
+    pub fn recover(&mut self, swap: &Swap) {
+        let mut contents = swap.rows.join("\n");
+        if !contents.is_empty() {
+            contents.push('\n');
+        }
+        let end = Position {
+            x: 0,
+            y: self.rows.len(),
+        };
+        self.replace(&Position::default(), &end, &contents);
+    }
+
+    pub fn write_swap(&mut self, cursor: &Position) -> Result<(), Error> {
+        if !self.swap_dirty {
+            return Ok(());
+        }
+        if let Some(path) = self.swap_path() {
+            Swap::write(&path, cursor, self.line_bytes())?;
+        }
+        self.swap_dirty = false;
+        Ok(())
+    }
+
+    pub fn remove_swap(&self) -> Result<(), Error> {
+        if let Some(path) = self.swap_path() {
+            Swap::remove(&path)?;
         }
         Ok(())
     }
@@ -104,11 +1092,305 @@ impl Document {
     }
 
     pub fn find(&self, query: &str) -> Option<Position> {
-        for (y, row) in self.rows.iter().enumerate() {
-            if let Some(x) = row.find(query) {
-                return Some(Position{ x, y });
+        let mut y: usize = 0;
+        for segment in self.rows.segments() {
+            match segment {
+                Segment::Rows(rows) => {
+                    for row in rows {
+                        if let Some(x) = row.find(query) {
+                            return Some(Position { x, y });
+                        }
+                        y = y.saturating_add(1);
+                    }
+                }
+                Segment::Lines(chunk) => {
+                    // Only decode from the first raw match on; the bytes may
+                    // match across a line break or invalid UTF-8.
+                    if let Some(first) = chunk.find(query) {
+                        for line in first..chunk.len() {
+                            if let Some(x) = chunk.row(line).and_then(|row| row.find(query)) {
+                                return Some(Position {
+                                    x,
+                                    y: y.saturating_add(line),
+                                });
+                            }
+                        }
+                    }
+                    y = y.saturating_add(chunk.len());
+                }
             }
         }
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, usize, char),
+        Delete(usize, usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let c = prop_oneof![
+            Just('a'),
+            Just('b'),
+            Just(' '),
+            Just('é'),
+            Just('\n'),
+        ];
+        prop_oneof![
+            (0..8_usize, 0..12_usize, c).prop_map(|(y, x, c)| Op::Insert(y, x, c)),
+            (0..8_usize, 0..12_usize).prop_map(|(y, x)| Op::Delete(y, x)),
+        ]
+    }
+
+    // The reference model: the rows as plain strings of chars.
+    fn apply(model: &mut Vec<Vec<char>>, op: &Op) {
+        match *op {
+            Op::Insert(y, x, c) => {
+                if y == model.len() {
+                    model.push(Vec::new());
+                    if c == '\n' {
+                        return;
+                    }
+                }
+                let row = match model.get_mut(y) {
+                    Some(row) => row,
+                    None => return,
+                };
+                let x = x.min(row.len());
+                if c == '\n' {
+                    let tail = row.split_off(x);
+                    model.insert(y + 1, tail);
+                } else {
+                    row.insert(x, c);
+                }
+            }
+            Op::Delete(y, x) => {
+                let len = model.len();
+                let row_len = match model.get(y) {
+                    Some(row) => row.len(),
+                    None => return,
+                };
+                if x < row_len {
+                    model[y].remove(x);
+                } else if y + 1 < len {
+                    let next = model.remove(y + 1);
+                    model[y].extend(next);
+                }
+            }
+        }
+    }
+
+    fn run(document: &mut Document, op: &Op) {
+        match *op {
+            Op::Insert(y, x, c) => document.insert(&Position { x, y }, c),
+            Op::Delete(y, x) => document.delete(&Position { x, y }),
+        }
+    }
+
+    fn text(model: &[Vec<char>]) -> Vec<String> {
+        model.iter().map(|row| row.iter().collect()).collect()
+    }
+
+    fn document(lines: &[&str]) -> Document {
+        Document::from_lines(&lines.iter().map(|line| line.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn comments_toggle_at_the_least_indented_column() {
+        let mut doc = document(&["fn f() {", "    x();", "", "}"]);
+        doc.toggle_comment(0, 3, "//");
+        assert_eq!(doc.lines(), vec!["// fn f() {", "//     x();", "", "// }"]);
+        doc.toggle_comment(1, 1, "//");
+        assert_eq!(doc.lines(), vec!["// fn f() {", "    x();", "", "// }"]);
+        doc.undo();
+        doc.undo();
+        assert_eq!(doc.lines(), vec!["fn f() {", "    x();", "", "}"]);
+    }
+
+    #[test]
+    fn indent_and_outdent_skip_empty_lines() {
+        let mut doc = document(&["a", "", "  b"]);
+        doc.indent_lines(0, 2, "    ");
+        assert_eq!(doc.lines(), vec!["    a", "", "      b"]);
+        doc.outdent_lines(0, 2, 4);
+        doc.outdent_lines(0, 2, 4);
+        assert_eq!(doc.lines(), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn line_commands_are_single_undo_steps() {
+        let mut doc = document(&["a", "b", "c"]);
+        assert!(doc.move_lines(1, 2, true));
+        assert_eq!(doc.lines(), vec!["b", "c", "a"]);
+        assert!(!doc.move_lines(0, 0, true));
+        assert!(doc.duplicate_lines(0, 1));
+        assert_eq!(doc.lines(), vec!["b", "c", "b", "c", "a"]);
+        doc.delete_lines(3, 4);
+        assert_eq!(doc.lines(), vec!["b", "c", "b"]);
+        assert!(doc.reverse_lines(0, 1));
+        assert_eq!(doc.lines(), vec!["c", "b", "b"]);
+        for _ in 0..4 {
+            doc.undo();
+        }
+        assert_eq!(doc.lines(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn brackets_match_across_rows() {
+        let doc = document(&["f(a[0], {", "  (b)", "})"]);
+        assert_eq!(doc.matching_bracket(&Position { x: 1, y: 0 }), Some(Position { x: 1, y: 2 }));
+        assert_eq!(doc.matching_bracket(&Position { x: 0, y: 2 }), Some(Position { x: 8, y: 0 }));
+        assert_eq!(doc.matching_bracket(&Position { x: 3, y: 0 }), Some(Position { x: 5, y: 0 }));
+        assert_eq!(doc.matching_bracket(&Position { x: 2, y: 0 }), None);
+        assert_eq!(doc.matching_bracket(&Position { x: 0, y: 1 }), None);
+    }
+
+    #[test]
+    fn paragraphs_end_at_blank_lines_and_other_markers() {
+        let doc = document(&["text", "# one", "# two", "#", "# three", "more"]);
+        assert_eq!(doc.paragraph(2), Some((1, 2)));
+        assert_eq!(doc.paragraph(3), None);
+        assert_eq!(doc.paragraph(5), Some((5, 5)));
+    }
+
+    #[test]
+    fn wrap_row_breaks_at_the_last_space_that_fits() {
+        let mut doc = document(&["  // aaa bbb ccc"]);
+        assert_eq!(doc.wrap_row(0, 20), None);
+        assert_eq!(doc.wrap_row(0, 13), Some((13, 5)));
+        assert_eq!(doc.lines(), vec!["  // aaa bbb", "  // ccc"]);
+    }
+
+    #[test]
+    fn join_trims_indentation() {
+        let mut doc = document(&["if x {  ", "    y", "", "    }"]);
+        assert!(doc.join_lines(0, 3));
+        assert_eq!(doc.lines(), vec!["if x { y }"]);
+    }
+
+    #[test]
+    fn sort_options() {
+        let lines = ["10 b", "9 a", "B", "a", "9 a"];
+        let sorted = |options: SortOptions| {
+            let mut doc = document(&lines);
+            doc.sort_lines(0, 4, options);
+            doc.lines().iter().map(|line| line.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(sorted(SortOptions::default()), vec!["10 b", "9 a", "9 a", "B", "a"]);
+        let options = SortOptions { numeric: true, ..SortOptions::default() };
+        assert_eq!(sorted(options), vec!["B", "a", "9 a", "9 a", "10 b"]);
+        let options = SortOptions { ignore_case: true, unique: true, ..SortOptions::default() };
+        assert_eq!(sorted(options), vec!["10 b", "9 a", "a", "B"]);
+    }
+
+    #[test]
+    fn tidying_trims_whitespace_and_trailing_rows() {
+        let path = std::env::temp_dir().join(format!("rustte-tidy-{}.txt", std::process::id()));
+        fs::write(&path, "a \nb \nc\n").unwrap();
+        let mut doc = Document::open(path.to_str().unwrap()).unwrap();
+        doc.insert(&Position { x: 1, y: 2 }, ' ');
+        doc.insert(&Position { x: 2, y: 2 }, '\n');
+        doc.insert(&Position { x: 0, y: 3 }, '\n');
+        let options = SaveOptions {
+            trim_trailing_whitespace: true,
+            trim_modified_only: true,
+            final_newline: true,
+        };
+        doc.tidy(options);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(doc.lines(), vec!["a ", "b ", "c"]);
+        doc.undo();
+        assert_eq!(doc.lines(), vec!["a ", "b ", "c ", "", ""]);
+        doc.tidy(SaveOptions { trim_modified_only: false, ..options });
+        assert_eq!(doc.lines(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn line_endings_and_byte_order_marks_survive_a_round_trip() {
+        let text = b"\xEF\xBB\xBFa\r\nb\r\n";
+        let mut doc = Document::from_reader(&text[..], UTF_8).unwrap();
+        assert_eq!(doc.lines(), vec!["a", "b"]);
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+        let mut written = Vec::new();
+        doc.write_to(&mut written).unwrap();
+        assert_eq!(written, text);
+        doc.set_line_ending(LineEnding::Lf);
+        doc.set_bom(false);
+        written.clear();
+        doc.write_to(&mut written).unwrap();
+        assert_eq!(written, b"a\nb\n");
+        doc.set_final_newline(false);
+        written.clear();
+        doc.write_to(&mut written).unwrap();
+        assert_eq!(written, b"a\nb");
+        let mut doc = Document::from_reader(&b"a\nb\n"[..], encoding_rs::WINDOWS_1252).unwrap();
+        doc.set_final_newline(false);
+        written.clear();
+        doc.write_to(&mut written).unwrap();
+        assert_eq!(written, b"a\nb");
+        let doc = Document::from_reader(&b"a\rb\r"[..], UTF_8).unwrap();
+        assert_eq!(doc.lines(), vec!["a", "b"]);
+        assert_eq!(doc.line_ending(), LineEnding::Cr);
+        written.clear();
+        doc.write_to(&mut written).unwrap();
+        assert_eq!(written, b"a\rb\r");
+    }
+
+    #[test]
+    fn line_endings_are_read_from_mapped_and_reloaded_files() {
+        let path = std::env::temp_dir().join(format!("rustte-endings-{}.txt", std::process::id()));
+        let filename = path.to_str().unwrap();
+        let text = "line\r\n".repeat(LARGE_FILE as usize / 6 + 1);
+        fs::write(&path, &text).unwrap();
+        let mut doc = Document::open(filename).unwrap();
+        while doc.is_indexing() {
+            doc.poll_index();
+        }
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+        let mut written = Vec::new();
+        doc.write_to(&mut written).unwrap();
+        assert!(written == text.as_bytes());
+        drop(doc);
+
+        fs::write(&path, "a\nb\n").unwrap();
+        let mut doc = Document::open(filename).unwrap();
+        fs::write(&path, "a\r\nb\r\n").unwrap();
+        doc.reload().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+    }
+
+    proptest! {
+        #[test]
+        fn edits_match_the_model(ops in prop::collection::vec(op(), 0..60)) {
+            let mut document = Document::default();
+            let mut model = Vec::new();
+            for op in &ops {
+                run(&mut document, op);
+                apply(&mut model, op);
+                prop_assert_eq!(document.lines(), text(&model));
+            }
+        }
+
+        #[test]
+        fn undo_restores_and_redo_replays(ops in prop::collection::vec(op(), 0..40)) {
+            let mut document = Document::default();
+            for op in &ops {
+                run(&mut document, op);
+            }
+            let edited: Vec<String> = document.lines().iter().map(|line| line.to_string()).collect();
+            while document.undo().is_some() {}
+            prop_assert!(document.is_empty());
+            prop_assert!(!document.is_dirty());
+            while document.redo().is_some() {}
+            prop_assert_eq!(document.lines(), edited);
+        }
+    }
+}
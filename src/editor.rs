@@ -1,19 +1,31 @@
+use crate::backend::{Backend, TermionBackend, CTRL_BACKSPACE, CTRL_DELETE, CTRL_LEFT, CTRL_RIGHT};
+use crate::brackets;
+use crate::config::Config;
+use crate::diff;
+use crate::indent;
+use crate::shell;
+use crate::document::SortOptions;
 use crate::Document;
+use crate::EditorConfig;
+use crate::row;
 use crate::Row;
 use crate::Terminal;
+use crate::whitespace;
+use crate::wrap;
 use std::env; 
 use std::cmp;
-use std::time::Duration;
+use std::collections::VecDeque;
+use std::mem;
+use std::path::Path;
 use std::time::Instant;
+use termion::color::Rgb;
 use termion::event::Key;
-use termion::color;
+use unicode_segmentation::UnicodeSegmentation;
 
-const STATUS_BG_COLOR: color::Rgb = color::Rgb(239, 239, 239);
-const STATUS_FG_COLOR: color::Rgb = color::Rgb(63, 63, 63);
 const VERSION: &str = env!("CARGO_PKG_VERSION");
-const QUIT_TIMES: u8 = 3;
+const HELP: &str = "HELP: Ctrs-f = find | Ctrl-s = save | Ctrl-q = quit";
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy, PartialEq, Debug)]
 pub struct Position {
     pub x: usize,
     pub y: usize,
@@ -32,79 +44,352 @@ impl StatusMessage {
         }
     }
 }
-pub struct Editor {
+// The settings for `document`: `defaults`, with what its .editorconfig
+// files say applied. Line endings and byte order marks are the document's
+// own business.
+fn settings(defaults: &Config, document: &mut Document) -> Config {
+    let mut config = defaults.clone();
+    if config.read_only {
+        document.set_read_only(true);
+    }
+    if let Some(name) = &document.name {
+        let editorconfig = EditorConfig::for_file(Path::new(name));
+        editorconfig.apply(&mut config);
+        editorconfig.apply_to_document(document);
+    }
+    config
+}
+
+// Prints the graphemes `start..end` of `row`, the first at screen column
+// `column`, and returns the column after them. With `whitespace`, the start
+// of the row's trailing whitespace and a color, invisible characters are
+// drawn as glyphs in that color; the row itself is left alone.
+fn print_graphemes<B: Backend>(
+    terminal: &mut Terminal<B>,
+    row: &Row,
+    start: usize,
+    end: usize,
+    column: usize,
+    tab_width: usize,
+    whitespace: Option<(usize, Rgb)>,
+) -> usize {
+    let end = cmp::min(end, row.len());
+    let (trailing, fg) = if let Some(whitespace) = whitespace {
+        whitespace
+    } else {
+        terminal.print(&row.render(start, end, column, tab_width));
+        let width = row.column(end, tab_width).saturating_sub(row.column(start, tab_width));
+        return column.saturating_add(width);
+    };
+    let (mut run, mut run_column, mut column) = (start, column, column);
+    for x in start..end {
+        let grapheme = row.slice(x, x.saturating_add(1));
+        let width = row::width(grapheme, column, tab_width);
+        if let Some(glyph) = whitespace::glyph(grapheme, x >= trailing) {
+            terminal.print(&row.render(run, x, run_column, tab_width));
+            terminal.set_fg_color(fg);
+            terminal.print(glyph.encode_utf8(&mut [0; 4]));
+            terminal.reset_fg_color();
+            // The rest of a tab stays blank.
+            terminal.print(&" ".repeat(width.saturating_sub(1)));
+            run = x.saturating_add(1);
+            run_column = column.saturating_add(width);
+        }
+        column = column.saturating_add(width);
+    }
+    terminal.print(&row.render(run, end, run_column, tab_width));
+    column
+}
+
+// A document that is open but not on screen.
+struct Buffer {
+    document: Document,
+    cursor_position: Position,
+    offset: Position,
+    config: Config,
+}
+
+pub struct Editor<B: Backend = TermionBackend> {
     quit: bool,
-    terminal: Terminal,
+    terminal: Terminal<B>,
     cursor_position: Position,
     offset: Position,
     document: Document,
     status_msg: StatusMessage,
     quit_times: u8,
+    last_swap: Instant,
+    last_disk_check: Instant,
+    mark: Option<Position>,
+    // The settings given to the editor, and those of the current document,
+    // which its .editorconfig files may change.
+    defaults: Config,
+    config: Config,
+    buffers: VecDeque<Buffer>,
+    buffer_index: usize,
+    // The bracket at the cursor and its match, as last drawn.
+    brackets: Option<(Position, Position)>,
+    // With soft wrap, the screen lines of the top row that are scrolled
+    // off above `offset.y`.
+    wrap_offset: usize,
 }
 
-impl Editor {
-    pub fn default() -> Self {
-        let args: Vec<String> = env::args().collect();
-        let mut inital_status = 
-            String::from("HELP: Ctrs-f = find | Ctrl-s = save | Ctrl-q = quit");
-        let document = if let Some(file_name) = args.get(1) {
-            let doc = Document::open(file_name);
-            if let Ok(doc) = doc {
-                doc
-            } else {
-                inital_status = format!("ERR: Could not open file: {}", file_name);
-                Document::default()
-            }
-        } else {
-            Document::default()
-        };
+impl<B: Backend> Editor<B> {
+    pub fn new(terminal: Terminal<B>, mut document: Document, defaults: Config) -> Self {
+        let config = settings(&defaults, &mut document);
         Self{ 
             quit: false,
-            terminal: Terminal::default().expect("Failed to initialize terminal"),
+            terminal,
             document,
             cursor_position: Position::default(),
             offset: Position::default(),
-            status_msg: StatusMessage::from(inital_status),
-            quit_times: QUIT_TIMES,
+            status_msg: StatusMessage::from(HELP.to_string()),
+            quit_times: config.quit_times,
+            last_swap: Instant::now(),
+            last_disk_check: Instant::now(),
+            mark: None,
+            defaults,
+            config,
+            buffers: VecDeque::new(),
+            buffer_index: 0,
+            brackets: None,
+            wrap_offset: 0,
         }
     }
 
-    pub fn run(&mut self) {
+    // Opens `file_name`, starting on an empty document with an error
+    // message if it can't be read.
+    pub fn open(terminal: Terminal<B>, file_name: &str, config: Config) -> Self {
+        match Document::open(file_name) {
+            Ok(document) => Self::new(terminal, document, config),
+            Err(_) => {
+                let mut editor = Self::new(terminal, Document::default(), config);
+                editor.status_msg =
+                    StatusMessage::from(format!("ERR: Could not open file: {}", file_name));
+                editor
+            }
+        }
+    }
 
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    pub fn document_mut(&mut self) -> &mut Document {
+        &mut self.document
+    }
+
+    pub fn cursor_position(&self) -> Position {
+        self.cursor_position
+    }
+
+    pub fn set_cursor_position(&mut self, position: Position) {
+        self.move_to(position);
+        self.scroll();
+    }
+
+    // Opens another document behind the current one; Alt-n and Alt-p cycle
+    // through them.
+    pub fn add_document(&mut self, mut document: Document, cursor_position: Position) {
+        let config = settings(&self.defaults, &mut document);
+        self.buffers.push_back(Buffer {
+            document,
+            cursor_position,
+            offset: Position::default(),
+            config,
+        });
+    }
+
+    // Gives up the current document, restoring the terminal.
+    pub fn into_document(self) -> Document {
+        self.document
+    }
+
+    pub fn terminal(&self) -> &Terminal<B> {
+        &self.terminal
+    }
+
+    pub fn run(&mut self) -> Result<(), std::io::Error> {
+        let result = self.event_loop();
+        if result.is_err() {
+            // Best effort: the swap file is the only copy of unsaved work now.
+            let _ = self.document.write_swap(&self.cursor_position);
+        }
+        result
+    }
+
+    fn event_loop(&mut self) -> Result<(), std::io::Error> {
         loop {
-            if let Err(error) = self.refresh_screen() {
-                die(&error);
+            self.document.poll_index();
+            // Recovery edits the document, which has to wait for the index.
+            if !self.document.is_indexing() {
+                self.check_swap()?;
             }
+            self.refresh_screen()?;
             if self.quit {
-                break;
+                let _ = self.document.remove_swap();
+                for buffer in &self.buffers {
+                    let _ = buffer.document.remove_swap();
+                }
+                return Ok(());
+            }
+            self.process_keypress()?;
+            self.write_swap();
+            self.check_disk()?;
+        }
+    }
+
+    fn check_swap(&mut self) -> Result<(), std::io::Error> {
+        let swap = if let Some(swap) = self.document.take_swap() {
+            swap
+        } else {
+            return Ok(());
+        };
+        loop {
+            let choice = self.ask("Swap file found! (r)ecover, (d)iff, (x) discard, Esc = keep for later")?;
+            match choice {
+                Some('r') if self.document.is_read_only() => {
+                    self.document.keep_swap();
+                    self.status_msg = StatusMessage::from(
+                        "Read-only, swap file kept. Alt-r allows editing.".to_string(),
+                    );
+                    return Ok(());
+                }
+                Some('r') => {
+                    let Position { x, y } = swap.cursor;
+                    self.document.recover(&swap);
+                    let y = cmp::min(y, self.document.len());
+                    let x = self.document.row(y).map_or(0, |row| cmp::min(x, row.len()));
+                    self.cursor_position = Position { x, y };
+                    self.scroll();
+                    self.status_msg = StatusMessage::from("Recovered from swap file.".to_string());
+                    return Ok(());
+                }
+                Some('d') => {
+                    let diff = diff::unified(&self.document.lines(), &swap.rows);
+                    self.show_lines(&diff, "Diff of file (-) against swap (+). Arrows scroll, Enter returns.")?;
+                }
+                Some('x') => {
+                    if self.document.remove_swap().is_err() {
+                        self.status_msg = StatusMessage::from("ERR: Could not remove swap file".to_string());
+                    } else {
+                        self.status_msg = StatusMessage::from("Swap file discarded.".to_string());
+                    }
+                    return Ok(());
+                }
+                None => {
+                    self.document.keep_swap();
+                    self.status_msg = StatusMessage::from(String::new());
+                    return Ok(());
+                }
+                _ => (),
             }
-            if let Err(error) = self.process_keypress() {
-                die(&error);
+        }
+    }
+
+    fn write_swap(&mut self) {
+        if self.last_swap.elapsed() < self.config.swap_interval {
+            return;
+        }
+        self.last_swap = Instant::now();
+        if self.document.write_swap(&self.cursor_position).is_err() {
+            self.status_msg = StatusMessage::from("ERR: Could not write swap file".to_string());
+        }
+    }
+
+    fn check_disk(&mut self) -> Result<(), std::io::Error> {
+        if self.last_disk_check.elapsed() < self.config.disk_check_interval {
+            return Ok(());
+        }
+        self.last_disk_check = Instant::now();
+        if !self.document.changed_on_disk().unwrap_or(false) {
+            return Ok(());
+        }
+        if self.document.is_dirty() {
+            self.resolve_disk_change(false)?;
+        } else {
+            self.reload();
+        }
+        Ok(())
+    }
+
+    // Returns whether the buffer should still be written to disk.
+    fn resolve_disk_change(&mut self, saving: bool) -> Result<bool, std::io::Error> {
+        let question = if saving {
+            "File changed on disk! (o)verwrite, (r)eload, (d)iff, Esc = cancel"
+        } else {
+            "File changed on disk! (r)eload, (k)eep mine, (d)iff"
+        };
+        loop {
+            match self.ask(question)? {
+                Some('r') => {
+                    self.reload();
+                    return Ok(false);
+                }
+                Some('o') if saving => {
+                    return Ok(true);
+                }
+                Some('k') if !saving => {
+                    if self.document.ignore_disk_changes().is_err() {
+                        self.status_msg = StatusMessage::from("ERR: Could not read file".to_string());
+                    } else {
+                        self.status_msg = StatusMessage::from(String::new());
+                    }
+                    return Ok(false);
+                }
+                Some('d') => {
+                    if let Ok(disk) = self.document.disk_lines() {
+                        let diff = diff::unified(&disk, &self.document.lines());
+                        self.show_lines(&diff, "Diff of disk (-) against buffer (+). Arrows scroll, Enter returns.")?;
+                    }
+                }
+                None if saving => {
+                    self.status_msg = StatusMessage::from("Save aborted.".to_string());
+                    return Ok(false);
+                }
+                _ => (),
             }
         }
     }
 
-    fn refresh_screen(&self) -> Result<(), std::io::Error> {
-        Terminal::cursor_hide();
-        Terminal::cursor_position(&Position::default());
+    fn reload(&mut self) {
+        if self.document.reload().is_err() {
+            self.status_msg = StatusMessage::from("ERR: Could not reload file".to_string());
+            return;
+        }
+        if let Some(name) = &self.document.name {
+            EditorConfig::for_file(Path::new(name)).apply_to_document(&mut self.document);
+        }
+        let y = cmp::min(self.cursor_position.y, self.document.len());
+        let x = self.document.row(y).map_or(0, |row| cmp::min(self.cursor_position.x, row.len()));
+        self.cursor_position = Position { x, y };
+        self.scroll();
+        self.status_msg = StatusMessage::from("File reloaded from disk.".to_string());
+    }
+
+    fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
+        self.terminal.cursor_hide();
+        self.terminal.cursor_position(&Position::default());
         if self.quit {
-            Terminal::clear_screen();
-            println!("Adios. \r");
+            self.terminal.clear_screen();
+            self.terminal.println("Adios. ");
         } else {
             self.draw_rows();
             self.draw_status_bar();
             self.draw_msg_bar();
-            Terminal::cursor_position(&Position {
-                x: self.cursor_position.x.saturating_sub(self.offset.x),
-                y: self.cursor_position.y.saturating_sub(self.offset.y),
-
-            });
+            let cursor = self.cursor_on_screen();
+            self.terminal.cursor_position(&cursor);
         }
-        Terminal::cursor_show();
-        Terminal::flush()
+        self.terminal.cursor_show();
+        self.terminal.flush()
     }
 
     fn save(&mut self) {
+        if self.document.is_read_only()
+            && self.ask("File is read-only! (y) save anyway, Esc = cancel").unwrap_or(None) != Some('y')
+        {
+            self.status_msg = StatusMessage::from("Save aborted.".to_string());
+            return;
+        }
         if self.document.name.is_none() {
             let new_name = self.prompt("Save as: ").unwrap_or(None);
             if new_name.is_none() {
@@ -112,7 +397,15 @@ impl Editor {
                 return;
             }
             self.document.name = new_name;
+            self.config = settings(&self.defaults, &mut self.document);
+        }
+        if self.document.changed_on_disk().unwrap_or(false)
+            && !self.resolve_disk_change(true).unwrap_or(false)
+        {
+            return;
         }
+        self.document.tidy(self.config.save_options());
+        self.move_to(self.cursor_position);
         if self.document.save().is_ok() {
             self.status_msg = StatusMessage::from("File saved.".to_string());
         } else {
@@ -122,10 +415,27 @@ impl Editor {
     }
 
     fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let pressed_key = Terminal::read_key()?;
+        let pressed_key = if let Some(key) = self.terminal.read_key(self.config.input_timeout)? {
+            key
+        } else {
+            return Ok(());
+        };
         match pressed_key {
+            _ if is_edit(pressed_key) && self.document.is_indexing() => {
+                self.status_msg =
+                    StatusMessage::from("Still indexing, read-only until done.".to_string());
+            }
+            _ if is_edit(pressed_key)
+                && pressed_key != Key::Ctrl('s')
+                && self.document.is_read_only() =>
+            {
+                self.status_msg =
+                    StatusMessage::from("Read-only, Alt-r allows editing.".to_string());
+            }
             Key::Ctrl('q') => {
-                if self.quit_times > 0 && self.document.is_dirty() {
+                let dirty = self.document.is_dirty()
+                    || self.buffers.iter().any(|buffer| buffer.document.is_dirty());
+                if self.quit_times > 0 && dirty {
                     self.status_msg = StatusMessage::from(format!(
                             "WARNING! Changies will be lost. Press Ctrl-q {} more times to quit", 
                             self.quit_times));
@@ -138,19 +448,137 @@ impl Editor {
                 if let Some(query) = self.prompt("Search: ").unwrap_or(None) {
                     if let Some(position) = self.document.find(&query[..]) {
                         self.cursor_position = position;
+                    } else if self.document.is_indexing() {
+                        self.status_msg = StatusMessage::from(format!(
+                            "Not found :{} (still indexing).",
+                            query
+                        ));
                     } else {
                         self.status_msg = StatusMessage::from(format!("Not found :{}.",query));
                     }
                 }
             }
             Key::Ctrl('s') => self.save(),
+            Key::Ctrl('u') => {
+                if let Some(position) = self.document.undo() {
+                    self.mark = None;
+                    self.move_to(position);
+                } else {
+                    self.status_msg = StatusMessage::from("Nothing to undo.".to_string());
+                }
+            }
+            Key::Ctrl('r') => {
+                if let Some(position) = self.document.redo() {
+                    self.mark = None;
+                    self.move_to(position);
+                } else {
+                    self.status_msg = StatusMessage::from("Nothing to redo.".to_string());
+                }
+            }
+            Key::Null => {
+                if self.mark.take().is_none() {
+                    self.mark = Some(self.cursor_position);
+                    self.status_msg = StatusMessage::from("Mark set.".to_string());
+                }
+            }
+            Key::Esc => self.mark = None,
+            Key::Alt('|') => {
+                if let Some(command) = self.prompt("Filter through: ").unwrap_or(None) {
+                    self.filter(&command);
+                }
+            }
+            Key::Alt('!') => {
+                if let Some(command) = self.prompt("Insert output of: ").unwrap_or(None) {
+                    self.insert_output(&command);
+                }
+            }
+            Key::Alt('r') => {
+                let read_only = !self.document.is_read_only();
+                self.document.set_read_only(read_only);
+                self.status_msg = StatusMessage::from(
+                    if read_only { "Read-only." } else { "Editing allowed." }.to_string(),
+                );
+            }
+            Key::Alt('n') => self.switch_buffer(true),
+            Key::Alt('p') => self.switch_buffer(false),
+            Key::Ctrl('z') => {
+                // The process may never be resumed, so secure unsaved work first.
+                let _ = self.document.write_swap(&self.cursor_position);
+                self.terminal.suspend()?;
+            }
+            Key::Char('\t') if self.selection().is_some() => self.edit_lines(LineEdit::Indent),
+            Key::Alt('>') => self.edit_lines(LineEdit::Indent),
+            Key::Alt('<') | Key::BackTab => self.edit_lines(LineEdit::Outdent),
+            Key::Alt(';') => self.edit_lines(LineEdit::Comment),
+            Key::Alt('k') => self.line_command(LineCommand::MoveUp),
+            Key::Alt('j') => self.line_command(LineCommand::MoveDown),
+            Key::Alt('d') => self.line_command(LineCommand::Duplicate),
+            Key::Ctrl('k') => self.line_command(LineCommand::Delete),
+            Key::Alt('J') => self.line_command(LineCommand::Join),
+            Key::Alt('s') => self.line_command(LineCommand::Sort),
+            Key::Alt('v') => self.line_command(LineCommand::Reverse),
+            CTRL_LEFT => self.cursor_position = self.word_position(false),
+            CTRL_RIGHT => self.cursor_position = self.word_position(true),
+            CTRL_BACKSPACE => {
+                self.mark = None;
+                let start = self.word_position(false);
+                self.document.replace(&start, &self.cursor_position, "");
+                self.cursor_position = start;
+            }
+            CTRL_DELETE => {
+                self.mark = None;
+                let end = self.word_position(true);
+                self.document.replace(&self.cursor_position, &end, "");
+            }
+            Key::Alt('w') => {
+                self.config.soft_wrap = !self.config.soft_wrap;
+                self.offset.x = 0;
+                self.wrap_offset = 0;
+                self.status_msg = StatusMessage::from(
+                    if self.config.soft_wrap { "Soft wrap on." } else { "Soft wrap off." }.to_string(),
+                );
+            }
+            Key::Alt('q') => self.reflow(),
+            Key::Alt('.') => {
+                self.config.show_whitespace = !self.config.show_whitespace;
+                self.status_msg = StatusMessage::from(
+                    if self.config.show_whitespace { "Whitespace shown." } else { "Whitespace hidden." }
+                        .to_string(),
+                );
+            }
+            Key::Alt('a') => {
+                self.config.auto_wrap = !self.config.auto_wrap;
+                self.status_msg = StatusMessage::from(if self.config.auto_wrap {
+                    format!("Auto wrap at column {}.", self.config.wrap_column)
+                } else {
+                    "Auto wrap off.".to_string()
+                });
+            }
+            Key::Alt('m') => {
+                if let Some((_, other)) = self.brackets {
+                    self.move_to(other);
+                }
+            }
             Key::Char(c) => {
-                self.document.insert(&self.cursor_position, c);
-                self.move_cursor(Key::Right);
+                self.mark = None;
+                if !self.insert_paired(c) && !self.insert_indented(c) {
+                    self.document.insert(&self.cursor_position, c);
+                    self.move_cursor(Key::Right);
+                    if self.config.auto_wrap && !c.is_whitespace() {
+                        self.auto_wrap();
+                    }
+                }
+            }
+            Key::Delete => {
+                self.mark = None;
+                self.document.delete(&self.cursor_position);
             }
-            Key::Delete => self.document.delete(&self.cursor_position),
             Key::Backspace => {
-                if self.cursor_position.x > 0 || self.cursor_position.y > 0 {
+                self.mark = None;
+                if !self.backspace_pair()
+                    && !self.backspace_indent()
+                    && (self.cursor_position.x > 0 || self.cursor_position.y > 0)
+                {
                     self.move_cursor(Key::Left);
                     self.document.delete(&self.cursor_position);
                 }
@@ -166,15 +594,479 @@ impl Editor {
             _ => (),
         }
         self.scroll();
-        if self.quit_times < QUIT_TIMES {
-            self.quit_times = QUIT_TIMES;
+        if self.quit_times < self.config.quit_times {
+            self.quit_times = self.config.quit_times;
             self.status_msg = StatusMessage::from(String::new());
         }
         Ok(())
     }
 
-    fn scroll(&mut self) {
+    // The marked region, or None if there's no mark or it is empty.
+    fn selection(&self) -> Option<(Position, Position)> {
+        let mark = self.mark?;
+        let cursor = self.cursor_position;
+        if (mark.y, mark.x) < (cursor.y, cursor.x) {
+            Some((mark, cursor))
+        } else if mark == cursor {
+            None
+        } else {
+            Some((cursor, mark))
+        }
+    }
+
+    // The rows a line command works on: those the selection touches, or
+    // the cursor's. A selection ending at the start of a row leaves it out.
+    fn selected_lines(&self) -> (usize, usize) {
+        match self.selection() {
+            Some((start, end)) if end.x == 0 && end.y > start.y => (start.y, end.y.saturating_sub(1)),
+            Some((start, end)) => (start.y, end.y),
+            None => (self.cursor_position.y, self.cursor_position.y),
+        }
+    }
+
+    // Indents, outdents or comments the selected lines as one undo step,
+    // keeping the cursor and mark on the same text.
+    fn edit_lines(&mut self, edit: LineEdit) {
+        let (first, last) = self.selected_lines();
+        let row_len = |document: &Document, y: usize| document.row(y).map_or(0, Row::len);
+        let cursor_len = row_len(&self.document, self.cursor_position.y);
+        let mark_len = self.mark.map(|mark| row_len(&self.document, mark.y));
+        match edit {
+            LineEdit::Indent => self.document.indent_lines(first, last, &self.config.indent_unit()),
+            LineEdit::Outdent => self.document.outdent_lines(first, last, self.config.indent_width),
+            LineEdit::Comment => {
+                let file_type = self.document.file_type();
+                if let Some(token) = file_type.comment {
+                    self.document.toggle_comment(first, last, token);
+                } else {
+                    self.status_msg =
+                        StatusMessage::from(format!("No line comments in {} files.", file_type.name));
+                }
+            }
+        }
+        let shift = |position: Position, old_len: usize, document: &Document| Position {
+            x: position
+                .x
+                .saturating_add(row_len(document, position.y))
+                .saturating_sub(old_len),
+            y: position.y,
+        };
+        self.cursor_position = shift(self.cursor_position, cursor_len, &self.document);
+        if let (Some(mark), Some(mark_len)) = (self.mark, mark_len) {
+            self.mark = Some(shift(mark, mark_len, &self.document));
+        }
+    }
+
+    // Moves, copies, removes or reorders the selected lines as one undo
+    // step.
+    #[allow(clippy::integer_arithmetic)]
+    fn line_command(&mut self, command: LineCommand) {
+        let (first, last) = self.selected_lines();
+        let count = last.saturating_sub(first) + 1;
+        let shift = |editor: &mut Self, down: bool, rows: usize| {
+            let move_row = |position: Position| Position {
+                x: position.x,
+                y: if down { position.y + rows } else { position.y.saturating_sub(rows) },
+            };
+            editor.cursor_position = move_row(editor.cursor_position);
+            editor.mark = editor.mark.map(move_row);
+        };
+        match command {
+            LineCommand::MoveUp => {
+                if self.document.move_lines(first, last, true) {
+                    shift(self, false, 1);
+                }
+            }
+            LineCommand::MoveDown => {
+                if self.document.move_lines(first, last, false) {
+                    shift(self, true, 1);
+                }
+            }
+            LineCommand::Duplicate => {
+                if self.document.duplicate_lines(first, last) {
+                    shift(self, true, count);
+                }
+            }
+            LineCommand::Delete => {
+                self.document.delete_lines(first, last);
+                self.mark = None;
+                self.move_to(Position {
+                    x: self.cursor_position.x,
+                    y: first,
+                });
+            }
+            LineCommand::Join => {
+                let x = self.document.row(first).map_or(0, |row| row.as_str().trim_end().chars().count());
+                if self.document.join_lines(first, last) {
+                    self.mark = None;
+                    self.move_to(Position { x, y: first });
+                }
+            }
+            LineCommand::Sort => {
+                if let Some(options) = self.ask_sort_options().unwrap_or(None) {
+                    self.document.sort_lines(first, last, options);
+                }
+            }
+            LineCommand::Reverse => {
+                self.document.reverse_lines(first, last);
+            }
+        }
+        self.move_to(self.cursor_position);
+    }
+
+    fn ask_sort_options(&mut self) -> Result<Option<SortOptions>, std::io::Error> {
+        let mut options = SortOptions::default();
+        loop {
+            let on = |on: bool| if on { "x" } else { " " };
+            let question = format!(
+                "Sort: [{}] (i)gnore case [{}] (n)umeric [{}] (u)nique, Enter = sort",
+                on(options.ignore_case),
+                on(options.numeric),
+                on(options.unique)
+            );
+            match self.ask(&question)? {
+                Some('i') => options.ignore_case = !options.ignore_case,
+                Some('n') => options.numeric = !options.numeric,
+                Some('u') => options.unique = !options.unique,
+                Some('\n') => {
+                    self.status_msg = StatusMessage::from(String::new());
+                    return Ok(Some(options));
+                }
+                None => {
+                    self.status_msg = StatusMessage::from("Sort aborted.".to_string());
+                    return Ok(None);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn filter(&mut self, command: &str) {
+        let (start, end) = self.selection().unwrap_or((
+            Position::default(),
+            Position {
+                x: 0,
+                y: self.document.len(),
+            },
+        ));
+        let input = self.document.text(&start, &end);
+        let output = match shell::run(command, Some(input.clone())) {
+            Ok(output) => output,
+            Err(error) => {
+                self.status_msg = StatusMessage::from(format!("ERR: {}: {}", command, error));
+                return;
+            }
+        };
+        if !output.status.success() {
+            self.status_msg = StatusMessage::from(command_failure(command, &output));
+            return;
+        }
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        if !input.ends_with('\n') && text.ends_with('\n') {
+            text.pop();
+        }
+        self.document.replace(&start, &end, &text);
+        self.mark = None;
+        self.move_to(start);
+        self.status_msg = StatusMessage::from(command_stderr(command, &output));
+    }
+
+    fn insert_output(&mut self, command: &str) {
+        let output = match shell::run(command, None) {
+            Ok(output) => output,
+            Err(error) => {
+                self.status_msg = StatusMessage::from(format!("ERR: {}: {}", command, error));
+                return;
+            }
+        };
+        if !output.status.success() {
+            self.status_msg = StatusMessage::from(command_failure(command, &output));
+            return;
+        }
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        if text.ends_with('\n') {
+            text.pop();
+        }
+        let cursor = self.cursor_position;
+        let end = self.document.replace(&cursor, &cursor, &text);
+        self.move_to(end);
+        self.status_msg = StatusMessage::from(command_stderr(command, &output));
+    }
+
+    // Handles Enter and closing brackets, which keep the indentation in
+    // step with the code. Returns false for everything else.
+    #[allow(clippy::integer_arithmetic)]
+    fn insert_indented(&mut self, c: char) -> bool {
+        let at = self.cursor_position;
+        let (before, after) = match self.document.row(at.y) {
+            Some(row) => (row.slice(0, at.x).to_string(), row.slice(at.x, row.len()).to_string()),
+            None => return false,
+        };
+        let leading = indent::leading(&before);
+        if c == '\n' {
+            let mut indent = leading.to_string();
+            if indent::opens_block(&before, self.document.file_type()) {
+                indent.push_str(&self.config.indent_unit());
+            }
+            let mut text = format!("\n{}", indent);
+            if indent::splits_pair(&before, &after) {
+                text.push('\n');
+                text.push_str(leading);
+            }
+            // Whitespace after the cursor would only push the rest of the
+            // line past the new indentation.
+            let end = Position {
+                x: at.x + indent::leading(&after).chars().count(),
+                y: at.y,
+            };
+            self.document.replace(&at, &end, &text);
+            self.move_to(Position {
+                x: indent.chars().count(),
+                y: at.y + 1,
+            });
+            return true;
+        }
+        if brackets::opening(c).is_some() && !before.is_empty() && leading == before {
+            let text = format!("{}{}", indent::outdent(leading, self.config.indent_width), c);
+            let end = self.document.replace(&Position { x: 0, y: at.y }, &at, &text);
+            self.move_to(end);
+            return true;
+        }
+        false
+    }
+
+    // Where Ctrl-Right (forward) or Ctrl-Left would take the cursor: the
+    // next word end or previous word start in the row, else the row's end
+    // or start, else across the line break.
+    fn word_position(&self, forward: bool) -> Position {
         let Position { x, y } = self.cursor_position;
+        let row = self.document.row(y);
+        let len = row.map_or(0, Row::len);
+        if forward {
+            match row.and_then(|row| row.word_end_after(x)) {
+                Some(end) => Position { x: end, y },
+                None if x < len => Position { x: len, y },
+                None if y.saturating_add(1) < self.document.len() => Position { x: 0, y: y.saturating_add(1) },
+                None => self.cursor_position,
+            }
+        } else {
+            match row.and_then(|row| row.word_start_before(x)) {
+                Some(start) => Position { x: start, y },
+                None if x > 0 => Position { x: 0, y },
+                None if y > 0 => {
+                    let y = y.saturating_sub(1);
+                    Position {
+                        x: self.document.row(y).map_or(0, Row::len),
+                        y,
+                    }
+                }
+                None => self.cursor_position,
+            }
+        }
+    }
+
+    // Refills the selected lines, or the paragraph at the cursor, and
+    // leaves the cursor at the end of them.
+    fn reflow(&mut self) {
+        let (first, last) = if self.selection().is_some() {
+            self.selected_lines()
+        } else if let Some(paragraph) = self.document.paragraph(self.cursor_position.y) {
+            paragraph
+        } else {
+            return;
+        };
+        let len = self.document.len();
+        if self.document.reflow_lines(first, last, self.config.wrap_column) {
+            let last = last.saturating_add(self.document.len()).saturating_sub(len);
+            self.mark = None;
+            self.move_to(Position {
+                x: self.document.row(last).map_or(0, Row::len),
+                y: last,
+            });
+        }
+    }
+
+    // Moves the word being typed to a new line once it passes the wrap
+    // column.
+    fn auto_wrap(&mut self) {
+        let Position { x, y } = self.cursor_position;
+        if x <= self.config.wrap_column {
+            return;
+        }
+        if let Some((end, continued)) = self.document.wrap_row(y, self.config.wrap_column) {
+            if x >= end {
+                self.cursor_position = Position {
+                    x: x.saturating_sub(end).saturating_add(continued),
+                    y: y.saturating_add(1),
+                };
+            }
+        }
+    }
+
+    // The characters before and after the cursor.
+    fn around_cursor(&self) -> (Option<char>, Option<char>) {
+        let Position { x, y } = self.cursor_position;
+        let row = if let Some(row) = self.document.row(y) {
+            row
+        } else {
+            return (None, None);
+        };
+        let before = row.slice(x.saturating_sub(1), x).chars().last();
+        let after = row.slice(x, x.saturating_add(1)).chars().next();
+        (before, after)
+    }
+
+    // Typing an opening bracket or quote closes it too, and typing the
+    // closing half of a pair steps over it. Returns false if `c` was left
+    // for normal insertion.
+    #[allow(clippy::integer_arithmetic)]
+    fn insert_paired(&mut self, c: char) -> bool {
+        if !self.config.auto_pairs {
+            return false;
+        }
+        let at = self.cursor_position;
+        if brackets::opening(c).is_some() && self.closes_on_next_row(c) {
+            // The closer Enter put below when it split the pair: take out the
+            // blank row and step over that one instead of typing another.
+            let next = self.document.row(at.y + 1).map_or("", Row::as_str);
+            let x = indent::leading(next).chars().count() + 1;
+            self.document.replace(&Position { x: 0, y: at.y }, &Position { x: 0, y: at.y + 1 }, "");
+            self.move_to(Position { x, y: at.y });
+            return true;
+        }
+        let (before, after) = self.around_cursor();
+        if after == Some(c) && (brackets::opening(c).is_some() || brackets::is_quote(c)) {
+            self.move_to(Position { x: at.x + 1, y: at.y });
+            return true;
+        }
+        let close = if let Some(close) = brackets::pair_for(c) {
+            close
+        } else {
+            return false;
+        };
+        // Only where the pair can't swallow a word: before whitespace or a
+        // closing bracket, and for quotes not right after a word, as in
+        // "don't".
+        let open_space = after.map_or(true, |after| after.is_whitespace() || brackets::opening(after).is_some());
+        if !open_space || (brackets::is_quote(c) && before.map_or(false, char::is_alphanumeric)) {
+            return false;
+        }
+        self.document.replace(&at, &at, &format!("{}{}", c, close));
+        self.move_to(Position { x: at.x + 1, y: at.y });
+        true
+    }
+
+    // Whether the cursor is at the end of a blank row and the next one
+    // starts with `close` one indent level further out, as Enter leaves the
+    // closer of the block it opened. A closer further out belongs to an
+    // outer block.
+    fn closes_on_next_row(&self, close: char) -> bool {
+        let Position { x, y } = self.cursor_position;
+        let row = self.document.row(y).map_or("", Row::as_str);
+        let blank = !row.is_empty() && row.trim().is_empty() && x >= row.chars().count();
+        let next = self.document.row(y.saturating_add(1)).map_or("", Row::as_str);
+        let outdented = indent::outdent(row, self.config.indent_width) == indent::leading(next);
+        blank && outdented && next.trim_start().starts_with(close)
+    }
+
+    // Backspace between an empty pair takes out both halves.
+    #[allow(clippy::integer_arithmetic)]
+    fn backspace_pair(&mut self) -> bool {
+        let at = self.cursor_position;
+        match self.around_cursor() {
+            (Some(before), Some(after)) if self.config.auto_pairs && brackets::pair_for(before) == Some(after) => {
+                let start = Position { x: at.x - 1, y: at.y };
+                self.document.replace(&start, &Position { x: at.x + 1, y: at.y }, "");
+                self.cursor_position = start;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // The bracket at the cursor, or else right before it, and its match.
+    fn find_brackets(&self) -> Option<(Position, Position)> {
+        let at = self.cursor_position;
+        let before = Position { x: at.x.checked_sub(1)?, y: at.y };
+        [at, before].iter().find_map(|&position| {
+            self.document
+                .matching_bracket(&position)
+                .map(|other| (position, other))
+        })
+    }
+
+    // Backspace in leading spaces takes out a whole indent level.
+    #[allow(clippy::integer_arithmetic)]
+    fn backspace_indent(&mut self) -> bool {
+        let at = self.cursor_position;
+        let before = self.document.row(at.y).map_or("", |row| row.slice(0, at.x));
+        let width = indent::backspace_width(before, self.config.indent_width);
+        if width <= 1 {
+            return false;
+        }
+        let start = Position { x: at.x - width, y: at.y };
+        self.document.replace(&start, &at, "");
+        self.cursor_position = start;
+        true
+    }
+
+    fn move_to(&mut self, position: Position) {
+        let y = cmp::min(position.y, self.document.len());
+        let x = self.document.row(y).map_or(0, |row| cmp::min(position.x, row.len()));
+        self.cursor_position = Position { x, y };
+    }
+
+    // Puts the current document away and brings up the next (or previous)
+    // one, where the user left it.
+    #[allow(clippy::integer_arithmetic)]
+    fn switch_buffer(&mut self, forward: bool) {
+        if self.buffers.is_empty() {
+            self.status_msg = StatusMessage::from("No other files open.".to_string());
+            return;
+        }
+        let _ = self.document.write_swap(&self.cursor_position);
+        let current = Buffer {
+            document: mem::take(&mut self.document),
+            cursor_position: self.cursor_position,
+            offset: self.offset,
+            config: mem::take(&mut self.config),
+        };
+        let count = self.buffers.len().saturating_add(1);
+        let next = if forward {
+            self.buffers.push_back(current);
+            self.buffer_index = self.buffer_index.saturating_add(1) % count;
+            self.buffers.pop_front()
+        } else {
+            self.buffers.push_front(current);
+            self.buffer_index = self.buffer_index.checked_sub(1).unwrap_or(count.saturating_sub(1));
+            self.buffers.pop_back()
+        };
+        if let Some(next) = next {
+            self.document = next.document;
+            self.offset = next.offset;
+            self.config = next.config;
+            self.wrap_offset = 0;
+            self.mark = None;
+            self.move_to(next.cursor_position);
+            self.scroll();
+        }
+    }
+
+    // The screen column `position` is drawn at in its row, with tabs as
+    // wide as they are on screen.
+    fn column(&self, position: &Position) -> usize {
+        self.document
+            .row(position.y)
+            .map_or(position.x, |row| row.column(position.x, self.config.tab_width))
+    }
+
+    fn scroll(&mut self) {
+        if self.config.soft_wrap {
+            self.scroll_wrapped();
+            return;
+        }
+        let x = self.column(&self.cursor_position);
+        let y = self.cursor_position.y;
         let width = self.terminal.size().width as usize;
         let height = self.terminal.size().height as usize;
         let mut offset = &mut self.offset;
@@ -190,33 +1082,233 @@ impl Editor {
         }
     }
 
-    pub fn draw_row(&self, row: &Row) {
+    // Where each screen line of row `y` starts with soft wrap on.
+    fn wrap_breaks(&self, y: usize) -> Vec<usize> {
         let width = self.terminal.size().width as usize;
-        let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row)
+        let indent = self.config.wrap_marker.graphemes(true).count();
+        self.document
+            .row(y)
+            .map_or_else(|| vec![0], |row| wrap::breaks(row, width, indent, self.config.tab_width))
+    }
+
+    // Keeps the cursor's screen line on screen, counting in screen lines
+    // rather than rows.
+    fn scroll_wrapped(&mut self) {
+        let height = self.terminal.size().height as usize;
+        let Position { x, y } = self.cursor_position;
+        self.offset.x = 0;
+        self.wrap_offset = cmp::min(self.wrap_offset, self.wrap_breaks(self.offset.y).len().saturating_sub(1));
+        let line = wrap::line_of(&self.wrap_breaks(y), x);
+        if (y, line) < (self.offset.y, self.wrap_offset) {
+            self.offset.y = y;
+            self.wrap_offset = line;
+            return;
+        }
+        // The highest top that still shows the cursor's line.
+        let (mut top, mut top_line) = (y, line);
+        for _ in 1..height {
+            if top_line > 0 {
+                top_line = top_line.saturating_sub(1);
+            } else if top > 0 {
+                top = top.saturating_sub(1);
+                top_line = self.wrap_breaks(top).len().saturating_sub(1);
+            } else {
+                break;
+            }
+        }
+        if (self.offset.y, self.wrap_offset) < (top, top_line) {
+            self.offset.y = top;
+            self.wrap_offset = top_line;
+        }
+    }
+
+    // Up and Down with soft wrap go to the screen line above or below,
+    // keeping the column on screen.
+    #[allow(clippy::integer_arithmetic)]
+    fn move_visually(&self, down: bool) -> Position {
+        let Position { x, y } = self.cursor_position;
+        let marker = self.config.wrap_marker.graphemes(true).count();
+        let indent = |line: usize| if line > 0 { marker } else { 0 };
+        let breaks = self.wrap_breaks(y);
+        let line = wrap::line_of(&breaks, x);
+        let start = Position { x: breaks[line], y };
+        let column = self.column(&self.cursor_position) - self.column(&start) + indent(line);
+        let (y, line, breaks) = if down {
+            if line + 1 < breaks.len() {
+                (y, line + 1, breaks)
+            } else if y < self.document.len() {
+                (y + 1, 0, self.wrap_breaks(y + 1))
+            } else {
+                return self.cursor_position;
+            }
+        } else if line > 0 {
+            (y, line - 1, breaks)
+        } else if y > 0 {
+            let breaks = self.wrap_breaks(y - 1);
+            (y - 1, breaks.len() - 1, breaks)
+        } else {
+            return self.cursor_position;
+        };
+        let start = breaks[line];
+        // Only the last screen line of a row has room for the cursor after
+        // its final character.
+        let end = breaks
+            .get(line + 1)
+            .map_or_else(|| self.document.row(y).map_or(0, Row::len), |&next| next - 1);
+        let column = self.column(&Position { x: start, y }) + column.saturating_sub(indent(line));
+        let x = self
+            .document
+            .row(y)
+            .map_or(start, |row| row.at_column(column, self.config.tab_width));
+        Position { x: cmp::max(cmp::min(x, end), start), y }
+    }
+
+    // The cursor's place on screen.
+    fn cursor_on_screen(&self) -> Position {
+        let Position { x, y } = self.cursor_position;
+        let column = self.column(&self.cursor_position);
+        if !self.config.soft_wrap {
+            return Position {
+                x: column.saturating_sub(self.offset.x),
+                y: y.saturating_sub(self.offset.y),
+            };
+        }
+        let above: usize = (self.offset.y..y).map(|y| self.wrap_breaks(y).len()).sum();
+        let breaks = self.wrap_breaks(y);
+        let line = wrap::line_of(&breaks, x);
+        let marker = if line > 0 {
+            self.config.wrap_marker.graphemes(true).count()
+        } else {
+            0
+        };
+        let start = self.column(&Position { x: breaks[line], y });
+        Position {
+            x: column.saturating_sub(start).saturating_add(marker),
+            y: above.saturating_add(line).saturating_sub(self.wrap_offset),
+        }
+    }
+
+    pub fn draw_row(&mut self, y: usize) {
+        let width = self.terminal.size().width as usize;
+        let left = self.offset.x;
+        let right = left.saturating_add(width);
+        let tab_width = self.config.tab_width;
+        let (mut start, end) = self.document.row(y).map_or((0, 0), |row| {
+            (row.at_column(left, tab_width), row.at_column(right, tab_width))
+        });
+        // A tab that starts left of the screen shows only its blank end.
+        let start_column = self.column(&Position { x: start, y });
+        if start_column < left && start < end {
+            start = start.saturating_add(1);
+            let column = self.column(&Position { x: start, y });
+            self.terminal.print(&" ".repeat(column.saturating_sub(left)));
+        }
+        self.draw_span(y, start, end);
+    }
+
+    // Draws the graphemes `start..end` of row `y` and ends the line. Tabs
+    // are as wide as where they fall in the whole row makes them.
+    fn draw_span(&mut self, y: usize, start: usize, end: usize) {
+        let row = if let Some(row) = self.document.row(y) {
+            row
+        } else {
+            return;
+        };
+        let tab_width = self.config.tab_width;
+        // Columns with a background of their own, left to right.
+        let mut spans = Vec::new();
+        if let Some((from, to)) = self.selection() {
+            if from.y <= y && y <= to.y {
+                let sel_start = if y == from.y { from.x } else { 0 };
+                let sel_end = if y == to.y { to.x } else { row.len() };
+                spans.push((sel_start, sel_end, self.config.selection_bg));
+            }
+        } else if let Some((bracket, other)) = self.brackets {
+            let mut columns: Vec<usize> = [bracket, other]
+                .iter()
+                .filter(|position| position.y == y)
+                .map(|position| position.x)
+                .collect();
+            columns.sort_unstable();
+            for x in columns {
+                spans.push((x, x.saturating_add(1), self.config.match_bg));
+            }
+        }
+        // Not while typing at the end of the row, where a space is usually
+        // followed by more text.
+        let trailing = row.trailing_whitespace();
+        let typing = self.cursor_position.y == y && self.cursor_position.x >= trailing;
+        if self.config.show_trailing_whitespace && trailing < row.len() && !typing {
+            spans.push((trailing, row.len(), self.config.trailing_whitespace_bg));
+        }
+        let whitespace = self.config.show_whitespace.then(|| (trailing, self.config.whitespace_fg));
+        let mut x = start;
+        let mut column = row.column(start, tab_width);
+        for (from, to, bg) in spans {
+            let from = cmp::min(cmp::max(from, x), end);
+            let to = cmp::min(cmp::max(to, from), end);
+            column = print_graphemes(&mut self.terminal, row, x, from, column, tab_width, whitespace);
+            self.terminal.set_bg_color(bg);
+            column = print_graphemes(&mut self.terminal, row, from, to, column, tab_width, whitespace);
+            self.terminal.reset_bg_color();
+            x = to;
+        }
+        print_graphemes(&mut self.terminal, row, x, end, column, tab_width, whitespace);
+        self.terminal.println("");
     }
 
     #[allow(clippy::integer_arithmetic, clippy::integer_division)]
-    fn draw_rows(&self) {
-        let height = self.terminal.size().height - 1;
+    fn draw_rows(&mut self) {
+        self.brackets = self.find_brackets();
+        if self.config.soft_wrap {
+            self.draw_wrapped_rows();
+            return;
+        }
+        let height = self.terminal.size().height;
         for terminal_row in 0..height {
-            Terminal::clear_current_line();
-            if let Some(row) = self
-                .document
-                .row(self.offset.y.saturating_add(terminal_row as usize)) {
-                self.draw_row(row);
+            self.terminal.clear_current_line();
+            let y = self.offset.y.saturating_add(terminal_row as usize);
+            if y < self.document.len() {
+                self.draw_row(y);
             } else if self.document.is_empty() && terminal_row == height / 3 {
                 self.draw_welcome();
             } else {
-                println!("~\r");
+                self.terminal.println("~");
             }
         }
     }
 
-    fn draw_welcome(&self) {
-        let mut welcome_msg = format!("RustTe editor -- version {}\r", VERSION);
+    #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+    fn draw_wrapped_rows(&mut self) {
+        let height = self.terminal.size().height;
+        let marker = self.config.wrap_marker.clone();
+        let (mut y, mut line) = (self.offset.y, self.wrap_offset);
+        let mut breaks = self.wrap_breaks(y);
+        for terminal_row in 0..height {
+            self.terminal.clear_current_line();
+            if y < self.document.len() {
+                let start = breaks.get(line).copied().unwrap_or(0);
+                let end = breaks.get(line + 1).copied().unwrap_or(usize::MAX);
+                if line > 0 {
+                    self.terminal.print(&marker);
+                }
+                self.draw_span(y, start, end);
+                line += 1;
+                if line >= breaks.len() {
+                    y += 1;
+                    line = 0;
+                    breaks = self.wrap_breaks(y);
+                }
+            } else if self.document.is_empty() && terminal_row == height / 3 {
+                self.draw_welcome();
+            } else {
+                self.terminal.println("~");
+            }
+        }
+    }
+
+    fn draw_welcome(&mut self) {
+        let mut welcome_msg = format!("RustTe editor -- version {}", VERSION);
         let width = self.terminal.size().width as usize;
         let len = welcome_msg.len();
         #[allow(clippy::integer_arithmetic, clippy::integer_division)]
@@ -224,11 +1316,11 @@ impl Editor {
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_msg = format!("~{}{}", spaces, welcome_msg);
         welcome_msg.truncate(width);
-        println!("{}\r", welcome_msg);
+        self.terminal.println(&welcome_msg);
 
     }
     
-    fn draw_status_bar(&self) {
+    fn draw_status_bar(&mut self) {
         let mut status;
         let width = self.terminal.size().width as usize;
         let mod_indicator = if self.document.is_dirty() {
@@ -242,6 +1334,19 @@ impl Editor {
             file_name.truncate(20);
         }
         status = format!("{} - {} lines{}", file_name, self.document.len(), mod_indicator);
+        if let Some(percent) = self.document.indexing_progress() {
+            status.push_str(&format!(" (indexing {}%)", percent));
+        }
+        if self.document.is_read_only() {
+            status.push_str(" [RO]");
+        }
+        if !self.buffers.is_empty() {
+            status.push_str(&format!(
+                " [{}/{}]",
+                self.buffer_index.saturating_add(1),
+                self.buffers.len().saturating_add(1)
+            ));
+        }
         let line_indicator = format!(
             "{}/{}",
             self.cursor_position.y.saturating_add(1),
@@ -252,20 +1357,20 @@ impl Editor {
         status.push_str(&" ".repeat(width.saturating_sub(len)));
         status = format!("{}{}", status, line_indicator);
         status.truncate(width);
-        Terminal::set_bg_color(STATUS_BG_COLOR);
-        Terminal::set_fg_color(STATUS_FG_COLOR);
-        println!("{}\r",status);
-        Terminal::reset_bg_color();
-        Terminal::reset_fg_color();
+        self.terminal.set_bg_color(self.config.status_bg);
+        self.terminal.set_fg_color(self.config.status_fg);
+        self.terminal.println(&status);
+        self.terminal.reset_bg_color();
+        self.terminal.reset_fg_color();
     }
 
-    fn draw_msg_bar(&self) {
-        Terminal::clear_current_line();
+    fn draw_msg_bar(&mut self) {
+        self.terminal.clear_current_line();
         let msg = &self.status_msg;
-        if Instant::now() - msg.time < Duration::new(5, 0) {
+        if Instant::now() - msg.time < self.config.message_timeout {
             let mut text = msg.text.clone();
             text.truncate(self.terminal.size().width as usize);
-            print!("{}", text);
+            self.terminal.print(&text);
         }
     }
 
@@ -278,6 +1383,10 @@ impl Editor {
         } else {
             0
         };
+        if self.config.soft_wrap && (key == Key::Up || key == Key::Down) {
+            self.cursor_position = self.move_visually(key == Key::Down);
+            return;
+        }
         match key {
             Key::Up => y = y.saturating_sub(1),
             Key::Down => {
@@ -314,7 +1423,7 @@ impl Editor {
             }
             Key::PageDown => {
                 y = if y.saturating_add(terminal_height) < height {
-                    y.saturating_sub(terminal_height)
+                    y.saturating_add(terminal_height)
                 } else {
                     height
                 }
@@ -338,7 +1447,12 @@ impl Editor {
         loop {
             self.status_msg = StatusMessage::from(format!("{}{}", prompt, result));
             self.refresh_screen()?;
-            match   Terminal::read_key()? {
+            let key = if let Some(key) = self.terminal.read_key(self.config.input_timeout)? {
+                key
+            } else {
+                continue;
+            };
+            match key {
                 Key::Backspace => result.truncate(result.len().saturating_sub(1)),
                 Key::Char('\n') => break,
                 Key::Char(c) => {
@@ -359,9 +1473,280 @@ impl Editor {
         }
         Ok(Some(result))
     }
+
+    fn ask(&mut self, question: &str) -> Result<Option<char>, std::io::Error> {
+        self.status_msg = StatusMessage::from(question.to_string());
+        loop {
+            self.refresh_screen()?;
+            match self.terminal.read_key(self.config.input_timeout)? {
+                Some(Key::Char(c)) => return Ok(Some(c)),
+                Some(Key::Esc) => return Ok(None),
+                _ => (),
+            }
+        }
+    }
+
+    fn show_lines(&mut self, lines: &[String], help: &str) -> Result<(), std::io::Error> {
+        let document = mem::replace(&mut self.document, Document::from_lines(lines));
+        let cursor_position = mem::take(&mut self.cursor_position);
+        let offset = mem::take(&mut self.offset);
+        let result = self.view_only(help);
+        self.document = document;
+        self.cursor_position = cursor_position;
+        self.offset = offset;
+        result
+    }
+
+    fn view_only(&mut self, help: &str) -> Result<(), std::io::Error> {
+        loop {
+            self.status_msg = StatusMessage::from(help.to_string());
+            self.refresh_screen()?;
+            match self.terminal.read_key(self.config.input_timeout)? {
+                Some(key @ (Key::Up
+                | Key::Down
+                | Key::Left
+                | Key::Right
+                | Key::PageUp
+                | Key::PageDown
+                | Key::End
+                | Key::Home)) => {
+                    self.move_cursor(key);
+                    self.scroll();
+                }
+                Some(Key::Char('\n') | Key::Esc) => return Ok(()),
+                _ => (),
+            }
+        }
+    }
+}
+
+// Edits applied to each selected line in place.
+enum LineEdit {
+    Indent,
+    Outdent,
+    Comment,
+}
+
+enum LineCommand {
+    MoveUp,
+    MoveDown,
+    Duplicate,
+    Delete,
+    Join,
+    Sort,
+    Reverse,
+}
+
+// Keys that change the document or write it out.
+fn is_edit(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Char(_)
+            | Key::Delete
+            | Key::Backspace
+            | Key::Ctrl('s' | 'u' | 'r' | 'k')
+            | Key::Alt('q')
+            | CTRL_BACKSPACE
+            | CTRL_DELETE
+            | Key::Alt('|' | '!' | '>' | '<' | ';' | 'k' | 'j' | 'd' | 'J' | 's' | 'v')
+            | Key::BackTab
+    )
+}
+
+fn command_failure(command: &str, output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let reason = output
+        .status
+        .code()
+        .map_or_else(|| "killed by signal".to_string(), |code| format!("exit {}", code));
+    match stderr.lines().next() {
+        Some(line) => format!("ERR: {}: {}: {}", command, reason, line),
+        None => format!("ERR: {}: {}", command, reason),
+    }
+}
+
+fn command_stderr(command: &str, output: &std::process::Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stderr
+        .lines()
+        .next()
+        .map_or_else(String::new, |line| format!("{}: {}", command, line))
 }
 
-fn die(e: &std::io::Error) {
-    Terminal::clear_screen();
-    panic!("{}", e);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::HeadlessBackend;
+
+    fn run(keys: Vec<Key>) -> Editor<HeadlessBackend> {
+        let backend = HeadlessBackend::new(40, 10, keys);
+        let mut editor = Editor::new(Terminal::new(backend).unwrap(), Document::default(), Config::default());
+        // The script running out ends the session with an error.
+        assert!(editor.run().is_err());
+        editor
+    }
+
+    fn typed(text: &str) -> Vec<Key> {
+        text.chars().map(Key::Char).collect()
+    }
+
+    #[test]
+    fn swap_files_kept_for_later_survive_edits_and_quitting() {
+        let path = std::env::temp_dir().join(format!("rustte-kept-{}.txt", std::process::id()));
+        let filename = path.to_str().unwrap();
+        let swap_path = crate::Swap::path_for(filename);
+        let ours = crate::Swap::numbered_path_for(filename, 1);
+        std::fs::write(&path, "saved\n").unwrap();
+        crate::Swap::write(&swap_path, &Position::default(), [&b"unsaved"[..]].into_iter()).unwrap();
+        let config = Config {
+            swap_interval: std::time::Duration::ZERO,
+            ..Config::default()
+        };
+        let session = |keys: Vec<Key>| {
+            let backend = HeadlessBackend::new(40, 10, keys);
+            let document = Document::open(filename).unwrap();
+            Editor::new(Terminal::new(backend).unwrap(), document, config.clone()).run()
+        };
+        // Edits are still written to a swap file of their own, which
+        // survives the session ending without a quit.
+        let crashed = session(vec![Key::Esc, Key::Char('z')]);
+        let kept = crate::Swap::read(&swap_path).unwrap();
+        let written = crate::Swap::read(&ours).unwrap();
+        let mut keys = vec![Key::Esc, Key::Char('z')];
+        keys.extend([Key::Ctrl('q'); 4]);
+        let quit = session(keys);
+        let after_quit = crate::Swap::read(&swap_path).unwrap();
+        // The crashed session's swap file is left alone too.
+        let next = crate::Swap::numbered_path_for(filename, 2);
+        let written_after_quit = crate::Swap::read(&ours).unwrap();
+        let next_after_quit = next.exists();
+        std::fs::remove_file(&path).unwrap();
+        let _ = std::fs::remove_file(&swap_path);
+        let _ = std::fs::remove_file(&ours);
+        assert!(crashed.is_err());
+        assert_eq!(kept.map(|swap| swap.rows), Some(vec!["unsaved".to_string()]));
+        assert_eq!(written.map(|swap| swap.rows), Some(vec!["zsaved".to_string()]));
+        assert!(quit.is_ok());
+        assert_eq!(after_quit.map(|swap| swap.rows), Some(vec!["unsaved".to_string()]));
+        assert_eq!(written_after_quit.map(|swap| swap.rows), Some(vec!["zsaved".to_string()]));
+        assert!(!next_after_quit);
+    }
+
+    #[test]
+    fn typing_shows_text_and_modified_status() {
+        let editor = run(typed("hello\nworld"));
+        let backend = editor.terminal().backend();
+        let lines = backend.lines();
+        assert_eq!(lines[0], "hello");
+        assert_eq!(lines[1], "world");
+        assert!(lines[8].starts_with("[Unnamed] - 2 lines (modified)"));
+        assert!(lines[8].ends_with("2/2"));
+        assert_eq!(backend.cursor(), Some(Position { x: 5, y: 1 }));
+    }
+
+    #[test]
+    fn search_moves_the_cursor_to_the_match() {
+        let mut keys = typed("one\ntwo\nthree");
+        keys.push(Key::Ctrl('f'));
+        keys.extend(typed("wo\n"));
+        let editor = run(keys);
+        assert_eq!(editor.terminal().backend().cursor(), Some(Position { x: 1, y: 1 }));
+    }
+
+    #[test]
+    fn moving_above_the_top_scrolls() {
+        let text: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        let mut keys = typed(&text.join("\n"));
+        keys.extend(std::iter::repeat(Key::Up).take(15));
+        let editor = run(keys);
+        let lines = editor.terminal().backend().lines();
+        assert_eq!(lines[0], "5");
+        assert_eq!(lines[7], "12");
+        assert!(lines[8].ends_with("5/20"));
+    }
+
+    #[test]
+    fn read_only_buffers_refuse_edits() {
+        let mut keys = typed("ab");
+        keys.push(Key::Alt('r'));
+        keys.extend(typed("cd"));
+        let editor = run(keys);
+        let lines = editor.terminal().backend().lines();
+        assert_eq!(lines[0], "ab");
+        assert!(lines[8].starts_with("[Unnamed] - 1 lines (modified) [RO]"));
+        assert_eq!(lines[9], "Read-only, Alt-r allows editing.");
+    }
+
+    #[test]
+    fn matching_brackets_are_highlighted_and_jumped_to() {
+        let mut keys = typed("f(a,\nb");
+        keys.extend([Key::Up, Key::Home, Key::Right, Key::Alt('m')]);
+        let editor = run(keys);
+        let backend = editor.terminal().backend();
+        assert_eq!(backend.cursor(), Some(Position { x: 1, y: 1 }));
+        let bg = |x, y| backend.screen().cell(x, y).and_then(|cell| cell.style.bg);
+        assert_eq!(bg(1, 0), Some(Config::default().match_bg));
+        assert_eq!(bg(1, 1), Some(Config::default().match_bg));
+        assert_eq!(bg(0, 0), None);
+    }
+
+    #[test]
+    fn trailing_whitespace_is_highlighted_away_from_the_cursor() {
+        let editor = run(typed("a  \nb "));
+        let backend = editor.terminal().backend();
+        let bg = |x, y| backend.screen().cell(x, y).and_then(|cell| cell.style.bg);
+        assert_eq!(bg(0, 0), None);
+        assert_eq!(bg(1, 0), Some(Config::default().trailing_whitespace_bg));
+        assert_eq!(bg(2, 0), Some(Config::default().trailing_whitespace_bg));
+        assert_eq!(bg(1, 1), None);
+    }
+
+    #[test]
+    fn visible_whitespace_only_changes_the_screen() {
+        let mut keys = typed("a\tb  \n");
+        keys.push(Key::Alt('.'));
+        let editor = run(keys);
+        let backend = editor.terminal().backend();
+        let cell = |x| backend.screen().cell(x, 0).cloned();
+        let fg = Some(Config::default().whitespace_fg);
+        assert_eq!(cell(1).map(|cell| (cell.grapheme, cell.style.fg)), Some(("→".to_string(), fg)));
+        // The tab reaches column 4, where the b is.
+        assert_eq!(cell(5).map(|cell| (cell.grapheme, cell.style.fg)), Some(("·".to_string(), fg)));
+        assert_eq!(cell(4).map(|cell| (cell.grapheme, cell.style.fg)), Some(("b".to_string(), None)));
+        assert_eq!(editor.document().lines(), vec!["a\tb  ", ""]);
+    }
+
+    #[test]
+    fn closers_of_outer_blocks_are_not_stepped_over() {
+        let editor = run(typed("if a {\n}"));
+        assert_eq!(editor.document().lines(), vec!["if a {", "}"]);
+        assert_eq!(editor.cursor_position, Position { x: 1, y: 1 });
+        // Closing the inner block on a blank row above the outer closer.
+        let mut keys = typed("fn a() {\nif b {");
+        keys.push(Key::Delete);
+        keys.extend(typed("\nc\n}"));
+        let editor = run(keys);
+        assert_eq!(editor.document().lines(), vec!["fn a() {", "    if b {", "        c", "    }", "}"]);
+    }
+
+    #[test]
+    fn tabs_are_as_wide_for_the_cursor_as_on_screen() {
+        let line = format!("{}ab", "\t".repeat(10));
+        let editor = run(typed(&line));
+        let cell = |x| editor.terminal().backend().screen().cell(x, 0).map(|cell| cell.grapheme.clone());
+        // The row ends at column 42, so the view scrolls by 3.
+        assert_eq!(editor.cursor_on_screen(), Position { x: 39, y: 0 });
+        assert_eq!(cell(37).as_deref(), Some("a"));
+
+        let mut keys = typed(&line);
+        keys.extend([Key::Alt('w'), Key::Up]);
+        let editor = run(keys);
+        assert_eq!(editor.cursor_position, Position { x: 1, y: 0 });
+        assert_eq!(editor.cursor_on_screen(), Position { x: 4, y: 0 });
+        let mut keys = typed(&line);
+        keys.extend([Key::Alt('w'), Key::Up, Key::Down]);
+        let editor = run(keys);
+        assert_eq!(editor.cursor_position, Position { x: 12, y: 0 });
+        assert_eq!(editor.cursor_on_screen(), Position { x: 4, y: 1 });
+    }
 }
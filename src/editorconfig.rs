@@ -0,0 +1,380 @@
+use crate::config::Config;
+use crate::document::LineEnding;
+use crate::Document;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{self, Path};
+
+const FILE_NAME: &str = ".editorconfig";
+
+// The properties the .editorconfig files above a file give it, nearer files
+// and later sections winning. Unknown properties and values are ignored.
+#[derive(Default, Debug)]
+pub struct EditorConfig {
+    properties: HashMap<String, String>,
+}
+
+impl EditorConfig {
+    pub fn for_file(path: &Path) -> Self {
+        let path = path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut files = Vec::new();
+        let mut dir = path.parent();
+        while let Some(current) = dir {
+            if let Ok(contents) = fs::read_to_string(current.join(FILE_NAME)) {
+                let file = parse(&contents);
+                let root = file.root;
+                files.push((current, file));
+                if root {
+                    break;
+                }
+            }
+            dir = current.parent();
+        }
+        let mut properties = HashMap::new();
+        for (dir, file) in files.iter().rev() {
+            let relative = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy();
+            for section in file.sections.iter().filter(|section| section.matches(&relative)) {
+                for (key, value) in &section.properties {
+                    if value == "unset" {
+                        properties.remove(key);
+                    } else {
+                        properties.insert(key.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        Self { properties }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.properties.get(key).map(String::as_str)
+    }
+
+    fn number(&self, key: &str) -> Option<usize> {
+        self.get(key).and_then(|value| value.parse().ok()).filter(|&number| number > 0)
+    }
+
+    fn flag(&self, key: &str) -> Option<bool> {
+        match self.get(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        }
+    }
+
+    // Overrides the settings the properties cover.
+    pub fn apply(&self, config: &mut Config) {
+        match self.get("indent_style") {
+            Some("tab") => config.indent_tabs = true,
+            Some("space") => config.indent_tabs = false,
+            _ => {}
+        }
+        // indent_size may be "tab", and either size stands in for the other
+        // when it is missing.
+        let tab_width = self.number("tab_width");
+        let indent_size = match self.get("indent_size") {
+            Some("tab") => tab_width,
+            _ => self.number("indent_size"),
+        };
+        if let Some(width) = indent_size.or(tab_width) {
+            config.indent_width = width;
+        }
+        if let Some(width) = tab_width.or(indent_size) {
+            config.tab_width = width;
+        }
+        if let Some(trim) = self.flag("trim_trailing_whitespace") {
+            config.trim_trailing_whitespace = trim;
+        }
+        if let Some(column) = self.number("max_line_length") {
+            config.wrap_column = column;
+        }
+    }
+
+    // Overrides how `document` is written, whatever its file had.
+    pub fn apply_to_document(&self, document: &mut Document) {
+        if let Some(line_ending) = self.line_ending() {
+            document.set_line_ending(line_ending);
+        }
+        if let Some(bom) = self.bom() {
+            document.set_bom(bom);
+        }
+        if let Some(final_newline) = self.flag("insert_final_newline") {
+            document.set_final_newline(final_newline);
+        }
+    }
+
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        match self.get("end_of_line")? {
+            "lf" => Some(LineEnding::Lf),
+            "crlf" => Some(LineEnding::CrLf),
+            "cr" => Some(LineEnding::Cr),
+            _ => None,
+        }
+    }
+
+    // The encoding files are read and written in. The UTF-16 charsets
+    // can't be written, so they are ignored.
+    pub fn encoding(&self) -> Option<&'static Encoding> {
+        match self.get("charset")? {
+            "utf-8" | "utf-8-bom" => Some(UTF_8),
+            "latin1" => Some(WINDOWS_1252),
+            _ => None,
+        }
+    }
+
+    // Whether files start with a byte order mark, if the charset says.
+    pub fn bom(&self) -> Option<bool> {
+        match self.get("charset")? {
+            "utf-8-bom" => Some(true),
+            "utf-8" | "latin1" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct File {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+struct Section {
+    glob: Vec<char>,
+    properties: Vec<(String, String)>,
+}
+
+impl Section {
+    // Globs with a slash are relative to the .editorconfig's directory;
+    // the others match the file name in any directory below it.
+    fn matches(&self, relative: &str) -> bool {
+        let text: Vec<char> = if self.glob.contains(&'/') {
+            relative.chars().collect()
+        } else {
+            relative.rsplit('/').next().unwrap_or(relative).chars().collect()
+        };
+        let glob = self.glob.strip_prefix(&['/']).unwrap_or(&self.glob);
+        glob_matches(glob, &text)
+    }
+}
+
+// Keys, and the values of the properties we know, are case-insensitive.
+fn parse(contents: &str) -> File {
+    let mut file = File::default();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            file.sections.push(Section {
+                glob: glob.chars().collect(),
+                properties: Vec::new(),
+            });
+        } else if let Some((key, value)) = line.split_once('=') {
+            let (key, value) = (key.trim().to_lowercase(), value.trim().to_lowercase());
+            match file.sections.last_mut() {
+                Some(section) => section.properties.push((key, value)),
+                None if key == "root" => file.root = value == "true",
+                None => {}
+            }
+        }
+    }
+    file
+}
+
+// EditorConfig globs: `*` within a path component, `**` across them, `?`,
+// `[set]`, `[!set]`, `{a,b}`, `{1..9}` and backslash escapes.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+fn glob_matches(glob: &[char], text: &[char]) -> bool {
+    let (first, rest) = match glob.split_first() {
+        Some(split) => split,
+        None => return text.is_empty(),
+    };
+    match first {
+        '*' if rest.first() == Some(&'*') => {
+            let rest = &rest[1..];
+            (0..=text.len()).any(|skip| glob_matches(rest, &text[skip..]))
+        }
+        '*' => (0..=text.len())
+            .take_while(|&skip| skip == 0 || text[skip - 1] != '/')
+            .any(|skip| glob_matches(rest, &text[skip..])),
+        '?' => text.first().map_or(false, |&c| c != '/') && glob_matches(rest, &text[1..]),
+        '[' => match rest.iter().position(|&c| c == ']') {
+            Some(end) if text.first().map_or(false, |&c| c != '/') => {
+                let (negated, set) = match rest[..end].split_first() {
+                    Some(('!', set)) => (true, set),
+                    _ => (false, &rest[..end]),
+                };
+                in_set(set, text[0]) != negated && glob_matches(&rest[end + 1..], &text[1..])
+            }
+            Some(_) => false,
+            None => literal('[', rest, text),
+        },
+        '{' => match closing_brace(rest) {
+            Some(end) => {
+                let after = &rest[end + 1..];
+                let alternatives = split_alternatives(&rest[..end]);
+                if alternatives.len() > 1 {
+                    alternatives
+                        .iter()
+                        .any(|alternative| glob_matches(&[alternative, after].concat(), text))
+                } else if let Some((low, high)) = range(&rest[..end]) {
+                    let digits = text
+                        .iter()
+                        .enumerate()
+                        .take_while(|&(i, &c)| c.is_ascii_digit() || (i == 0 && c == '-'))
+                        .count();
+                    (1..=digits).any(|len| {
+                        let number: String = text[..len].iter().collect();
+                        number.parse().map_or(false, |n: i64| low <= n && n <= high)
+                            && glob_matches(after, &text[len..])
+                    })
+                } else {
+                    literal('{', rest, text)
+                }
+            }
+            None => literal('{', rest, text),
+        },
+        '\\' => match rest.split_first() {
+            Some((&escaped, rest)) => literal(escaped, rest, text),
+            None => literal('\\', rest, text),
+        },
+        &c => literal(c, rest, text),
+    }
+}
+
+fn literal(c: char, glob: &[char], text: &[char]) -> bool {
+    text.split_first()
+        .map_or(false, |(&first, text)| first == c && glob_matches(glob, text))
+}
+
+// Whether `c` is in a `[...]` set such as `a-z_`.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+fn in_set(set: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if set.get(i + 1) == Some(&'-') && i + 2 < set.len() {
+            if set[i] <= c && c <= set[i + 2] {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+// The index of the `}` closing a brace whose contents start `glob`.
+fn closing_brace(glob: &[char]) -> Option<usize> {
+    let mut depth = 0_usize;
+    let mut escaped = false;
+    for (i, &c) in glob.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth = depth.saturating_add(1),
+            '}' if depth == 0 => return Some(i),
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
+// The comma-separated parts of a brace's contents, ignoring commas in
+// nested braces.
+fn split_alternatives(contents: &[char]) -> Vec<&[char]> {
+    let mut parts = Vec::new();
+    let mut depth = 0_usize;
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, &c) in contents.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth = depth.saturating_add(1),
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(contents.get(start..i).unwrap_or_default());
+                start = i.saturating_add(1);
+            }
+            _ => {}
+        }
+    }
+    parts.push(contents.get(start..).unwrap_or_default());
+    parts
+}
+
+// The bounds of a `{low..high}` range.
+fn range(contents: &[char]) -> Option<(i64, i64)> {
+    let contents: String = contents.iter().collect();
+    let (low, high) = contents.split_once("..")?;
+    Some((low.parse().ok()?, high.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        let section = Section {
+            glob: glob.chars().collect(),
+            properties: Vec::new(),
+        };
+        section.matches(path)
+    }
+
+    #[test]
+    fn globs() {
+        assert!(matches("*", "src/main.rs"));
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(!matches("*.rs", "src/main.rsx"));
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+        assert!(matches("/src/**.rs", "src/bin/main.rs"));
+        assert!(matches("*.{js,ts}", "app/index.ts"));
+        assert!(matches("Makefile", "sub/Makefile"));
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(!matches("file[!0-9].txt", "file7.txt"));
+        assert!(matches("part{1..12}.txt", "part10.txt"));
+        assert!(!matches("part{1..12}.txt", "part13.txt"));
+        assert!(matches("\\*.txt", "*.txt"));
+        assert!(!matches("\\*.txt", "a.txt"));
+    }
+
+    #[test]
+    fn later_sections_and_nearer_files_win() {
+        let dir = std::env::temp_dir().join(format!("rustte-editorconfig-{}", std::process::id()));
+        let sub = dir.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(
+            dir.join(FILE_NAME),
+            "root = true\n[*]\nindent_style = space\nindent_size = 2\nend_of_line = crlf\n\
+             [*.py]\nindent_size = 4\nmax_line_length = 79\n",
+        )
+        .unwrap();
+        fs::write(
+            sub.join(FILE_NAME),
+            "[*.py]\nindent_style = Tab\nindent_size = tab\ntab_width = 8\nend_of_line = unset\n",
+        )
+        .unwrap();
+        let top = EditorConfig::for_file(&dir.join("a.py"));
+        let nested = EditorConfig::for_file(&sub.join("b.py"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut config = Config::default();
+        top.apply(&mut config);
+        assert_eq!((config.indent_tabs, config.indent_width, config.wrap_column), (false, 4, 79));
+        assert_eq!(config.tab_width, 4);
+        assert_eq!(top.line_ending(), Some(LineEnding::CrLf));
+        let mut config = Config::default();
+        nested.apply(&mut config);
+        assert_eq!((config.indent_tabs, config.indent_width, config.wrap_column), (true, 8, 79));
+        assert_eq!(config.tab_width, 8);
+        assert_eq!(nested.line_ending(), None);
+    }
+}
@@ -0,0 +1,63 @@
+use std::path::Path;
+
+// What the editor knows about a language, picked by file name.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FileType {
+    pub name: &'static str,
+    // Whether a line ending in ':' opens a block, as in Python.
+    pub colon_indents: bool,
+    // What starts a line comment, if the language has them.
+    pub comment: Option<&'static str>,
+}
+
+impl Default for FileType {
+    fn default() -> Self {
+        Self {
+            name: "Text",
+            colon_indents: false,
+            comment: None,
+        }
+    }
+}
+
+// Name, file extensions (or whole names for files without one), whether ':'
+// opens a block, and the line comment token.
+const TYPES: &[(&str, &[&str], bool, &str)] = &[
+    ("Rust", &["rs"], false, "//"),
+    ("C", &["c", "h"], false, "//"),
+    ("C++", &["cc", "cpp", "cxx", "hh", "hpp"], false, "//"),
+    ("Go", &["go"], false, "//"),
+    ("Java", &["java"], false, "//"),
+    ("JavaScript", &["js", "mjs"], false, "//"),
+    ("TypeScript", &["ts"], false, "//"),
+    ("Python", &["py", "pyw"], true, "#"),
+    ("Shell", &["sh", "bash", "zsh"], false, "#"),
+    ("Ruby", &["rb"], false, "#"),
+    ("TOML", &["toml"], false, "#"),
+    ("YAML", &["yaml", "yml"], false, "#"),
+    ("Makefile", &["mk", "Makefile"], false, "#"),
+    ("Lua", &["lua"], false, "--"),
+    ("SQL", &["sql"], false, "--"),
+    ("Haskell", &["hs"], false, "--"),
+    ("Lisp", &["lisp", "el", "scm", "clj"], false, ";"),
+    ("INI", &["ini"], false, ";"),
+];
+
+impl FileType {
+    pub fn from_name(name: Option<&str>) -> Self {
+        let path = Path::new(name.unwrap_or(""));
+        let key = path
+            .extension()
+            .or_else(|| path.file_name())
+            .and_then(|key| key.to_str())
+            .unwrap_or("");
+        TYPES
+            .iter()
+            .find(|(_, keys, _, _)| keys.contains(&key))
+            .map_or_else(Self::default, |&(name, _, colon_indents, comment)| Self {
+                name,
+                colon_indents,
+                comment: Some(comment),
+            })
+    }
+}
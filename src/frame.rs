@@ -0,0 +1,110 @@
+use termion::color::Rgb;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Cell {
+    pub grapheme: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            grapheme: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+// A screenful of cells, one grapheme each.
+#[derive(Clone)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width.saturating_mul(height)],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn cell(&self, x: usize, y: usize) -> Option<&Cell> {
+        if x >= self.width {
+            return None;
+        }
+        self.cells.get(y.saturating_mul(self.width).saturating_add(x))
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(Cell::default());
+    }
+
+    pub fn clear_line(&mut self, y: usize) {
+        for x in 0..self.width {
+            self.put(x, y, " ", Style::default());
+        }
+    }
+
+    // Writes `text` from (x, y) on, cutting it off at the right edge.
+    // Returns the column after the last grapheme written.
+    pub fn print(&mut self, x: usize, y: usize, text: &str, style: Style) -> usize {
+        let mut x = x;
+        for grapheme in text.graphemes(true) {
+            // Control characters would move the real cursor behind our back.
+            let grapheme = if grapheme.chars().any(char::is_control) {
+                " "
+            } else {
+                grapheme
+            };
+            self.put(x, y, grapheme, style);
+            x = x.saturating_add(1);
+        }
+        x
+    }
+
+    fn put(&mut self, x: usize, y: usize, grapheme: &str, style: Style) {
+        if x >= self.width {
+            return;
+        }
+        let index = y.saturating_mul(self.width).saturating_add(x);
+        if let Some(cell) = self.cells.get_mut(index) {
+            if cell.grapheme != grapheme {
+                cell.grapheme.clear();
+                cell.grapheme.push_str(grapheme);
+            }
+            cell.style = style;
+        }
+    }
+
+    // Cells that differ from `previous`, in screen order. Every cell counts
+    // as changed if the sizes differ.
+    #[allow(clippy::integer_arithmetic, clippy::integer_division)]
+    pub fn changes<'a>(&'a self, previous: &'a Self) -> impl Iterator<Item = (usize, usize, &'a Cell)> {
+        let resized = self.width != previous.width || self.height != previous.height;
+        let width = self.width;
+        self.cells
+            .iter()
+            .enumerate()
+            .filter(move |&(index, cell)| resized || previous.cells.get(index) != Some(cell))
+            .map(move |(index, cell)| (index % width, index / width, cell))
+    }
+}
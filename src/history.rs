@@ -0,0 +1,142 @@
+use crate::Position;
+use unicode_segmentation::UnicodeSegmentation;
+
+// A single change to the document: `removed` was taken out at `at` and
+// `inserted` was put in its place. Rows are separated by '\n' in both.
+pub struct Edit {
+    pub at: Position,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn inserted_end(&self) -> Position {
+        end_of(self.at, &self.inserted)
+    }
+
+    pub fn removed_end(&self) -> Position {
+        end_of(self.at, &self.removed)
+    }
+
+    // Folds a follow-up keystroke into this edit so that a typed word or a
+    // run of Backspace/Delete is undone in one step.
+    fn merge(&mut self, next: &Self) -> bool {
+        if next.removed.is_empty() && self.removed.is_empty() {
+            let typing = self.inserted_end() == next.at
+                && !next.inserted.contains('\n')
+                && !self.inserted.ends_with('\n');
+            let word_ends = next.inserted.starts_with(char::is_whitespace)
+                && !self.inserted.ends_with(char::is_whitespace);
+            if typing && !word_ends {
+                self.inserted.push_str(&next.inserted);
+                return true;
+            }
+        } else if next.inserted.is_empty()
+            && self.inserted.is_empty()
+            && !next.removed.contains('\n')
+            && !self.removed.contains('\n')
+        {
+            if next.at == self.at {
+                self.removed.push_str(&next.removed);
+                return true;
+            }
+            if next.removed_end() == self.at {
+                self.at = next.at;
+                self.removed.insert_str(0, &next.removed);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+pub fn end_of(at: Position, text: &str) -> Position {
+    let mut lines = text.split('\n');
+    let first = lines.next().unwrap_or("");
+    let mut end = Position {
+        x: at.x.saturating_add(first.graphemes(true).count()),
+        y: at.y,
+    };
+    for line in lines {
+        end = Position {
+            x: line.graphemes(true).count(),
+            y: end.y.saturating_add(1),
+        };
+    }
+    end
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    group: Option<Vec<Edit>>,
+    sealed: bool,
+    // Number of undo steps at the last save; unreachable once we undo past
+    // it and then make a different change.
+    clean: usize,
+    diverged: bool,
+}
+
+impl History {
+    pub fn record(&mut self, edit: Edit) {
+        if self.redo.drain(..).next().is_some() && self.clean > self.undo.len() {
+            self.diverged = true;
+        }
+        if let Some(group) = &mut self.group {
+            group.push(edit);
+            return;
+        }
+        if !self.sealed {
+            if let Some([last]) = self.undo.last_mut().map(Vec::as_mut_slice) {
+                if last.merge(&edit) {
+                    return;
+                }
+            }
+        }
+        self.sealed = false;
+        self.undo.push(vec![edit]);
+    }
+
+    // Everything recorded until `end_group` is undone as one step.
+    pub fn begin_group(&mut self) {
+        self.group = Some(Vec::new());
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.is_empty() {
+                self.undo.push(group);
+                self.sealed = true;
+            }
+        }
+    }
+
+    pub fn take_undo(&mut self) -> Option<Vec<Edit>> {
+        self.sealed = true;
+        self.undo.pop()
+    }
+
+    pub fn push_undo(&mut self, group: Vec<Edit>) {
+        self.undo.push(group);
+    }
+
+    pub fn take_redo(&mut self) -> Option<Vec<Edit>> {
+        self.sealed = true;
+        self.redo.pop()
+    }
+
+    pub fn push_redo(&mut self, group: Vec<Edit>) {
+        self.redo.push(group);
+    }
+
+    pub fn mark_clean(&mut self) {
+        self.clean = self.undo.len();
+        self.diverged = false;
+        self.sealed = true;
+    }
+
+    pub fn is_clean(&self) -> bool {
+        !self.diverged && self.clean == self.undo.len()
+    }
+}
@@ -0,0 +1,85 @@
+use crate::brackets;
+use crate::filetype::FileType;
+
+// The whitespace a line starts with.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+pub fn leading(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+// Whether a line whose text before the cursor is `before` opens a block, so
+// the next line goes one level deeper.
+pub fn opens_block(before: &str, file_type: FileType) -> bool {
+    match before.trim_end().chars().last() {
+        Some(':') => file_type.colon_indents,
+        Some(c) => brackets::closing(c).is_some(),
+        None => false,
+    }
+}
+
+// Whether Enter between `before` and `after` splits a bracket pair, as in
+// `{|}`, and should leave the closing bracket on a line of its own.
+pub fn splits_pair(before: &str, after: &str) -> bool {
+    let open = before.trim_end().chars().last().and_then(brackets::closing);
+    open.is_some() && open == after.trim_start().chars().next()
+}
+
+// `indent` with one level taken off its end: a tab, or spaces back to the
+// previous multiple of `width`.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+pub fn outdent(indent: &str, width: usize) -> &str {
+    if let Some(rest) = indent.strip_suffix('\t') {
+        return rest;
+    }
+    let spaces = indent.len() - indent.trim_end_matches(' ').len();
+    let remove = match spaces % width.max(1) {
+        0 => width.min(spaces),
+        partial => partial,
+    };
+    &indent[..indent.len() - remove]
+}
+
+// How many spaces Backspace takes out of leading whitespace `before`, to get
+// back to the previous multiple of `width`.
+#[allow(clippy::integer_arithmetic)]
+pub fn backspace_width(before: &str, width: usize) -> usize {
+    if before.is_empty() || width == 0 || !before.bytes().all(|byte| byte == b' ') {
+        return 1;
+    }
+    match before.len() % width {
+        0 => width,
+        partial => partial,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_open_after_brackets_and_python_colons() {
+        let python = FileType::from_name(Some("script.py"));
+        let rust = FileType::from_name(Some("main.rs"));
+        assert!(opens_block("fn main() {", rust));
+        assert!(opens_block("    call(  ", rust));
+        assert!(!opens_block("match x:", rust));
+        assert!(opens_block("if x:", python));
+        assert!(!opens_block("x = 1", python));
+        assert!(splits_pair("f(", ")"));
+        assert!(splits_pair("{ ", "  }"));
+        assert!(!splits_pair("{", ")"));
+    }
+
+    #[test]
+    fn outdent_and_backspace_widths() {
+        assert_eq!(outdent("        ", 4), "    ");
+        assert_eq!(outdent("      ", 4), "    ");
+        assert_eq!(outdent("  ", 4), "");
+        assert_eq!(outdent("\t\t", 4), "\t");
+        assert_eq!(outdent("", 4), "");
+        assert_eq!(backspace_width("        ", 4), 4);
+        assert_eq!(backspace_width("      ", 4), 2);
+        assert_eq!(backspace_width("\t", 4), 1);
+        assert_eq!(backspace_width("", 4), 1);
+    }
+}
@@ -0,0 +1,44 @@
+#![warn(clippy::all, clippy::pedantic, clippy::restriction)]
+#![allow(
+    clippy::missing_docs_in_private_items,
+    clippy::implicit_return,
+    clippy::shadow_reuse,
+    clippy::print_stdout,
+    clippy::wildcard_enum_match_arm,
+    clippy::else_if_without_else,
+)]
+mod backend;
+mod config;
+mod document;
+mod row;
+mod editor;
+mod frame;
+mod terminal;
+mod swap;
+mod diff;
+mod history;
+mod rope;
+mod mapped;
+mod shell;
+mod filetype;
+mod indent;
+mod brackets;
+mod wrap;
+mod reflow;
+mod editorconfig;
+mod whitespace;
+pub use backend::{
+    Backend, HeadlessBackend, TermionBackend, CTRL_BACKSPACE, CTRL_DELETE, CTRL_LEFT, CTRL_RIGHT,
+};
+pub use config::Config;
+pub use document::{Document, LineEnding, SaveOptions, SortOptions};
+pub use editor::{Editor, Position};
+pub use editorconfig::EditorConfig;
+pub use filetype::FileType;
+pub use frame::{Cell, Frame, Style};
+pub use row::Row;
+pub use swap::Swap;
+pub use terminal::{Size, Terminal};
+pub use encoding_rs::Encoding;
+pub use termion::color::Rgb;
+pub use termion::event::Key;
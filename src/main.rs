@@ -7,16 +7,101 @@
     clippy::wildcard_enum_match_arm,
     clippy::else_if_without_else,
 )]
-mod document;
-mod row;
-mod editor;
-mod terminal;
-use editor::Editor;
-pub use document::Document;
-pub use row::Row;
-pub use terminal::Terminal;
-pub use editor::Position;
+mod cli;
+
+use cli::{Command, Options};
+use encoding_rs::UTF_8;
+use rustte::{Config, Document, Editor, EditorConfig, Encoding, Terminal};
+use std::env;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::process;
 
 fn main() {
-    Editor::default().run();
+    let options = match cli::parse(env::args().skip(1)) {
+        Ok(Command::Edit(options)) => options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("rustte {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(message) => {
+            eprintln!("rustte: {}", message);
+            eprintln!("Try 'rustte --help' for more information.");
+            process::exit(2);
+        }
+    };
+    if let Err(error) = edit(&options) {
+        eprintln!("rustte: {}", error);
+        process::exit(1);
+    }
+}
+
+fn edit(options: &Options) -> Result<(), Error> {
+    let mut config = match &options.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
+    };
+    config.read_only |= options.read_only;
+    // Everything that can fail is read before the terminal enters raw mode, so
+    // errors end up on a normal screen.
+    let mut documents = Vec::new();
+    for file in &options.files {
+        let document = if file.path == "-" {
+            Document::from_reader(io::stdin().lock(), options.encoding.unwrap_or(UTF_8))?
+        } else {
+            let encoding = options
+                .encoding
+                .or_else(|| EditorConfig::for_file(Path::new(&file.path)).encoding())
+                .unwrap_or(UTF_8);
+            open(&file.path, encoding)?
+        };
+        documents.push((document, file.position.unwrap_or_default()));
+    }
+    let output = if options.stdout { Some(take_stdout()?) } else { None };
+    let terminal = Terminal::default()?;
+    let mut documents = documents.into_iter();
+    let (document, position) = documents.next().unwrap_or_default();
+    let mut editor = Editor::new(terminal, document, config);
+    editor.set_cursor_position(position);
+    for (document, position) in documents {
+        editor.add_document(document, position);
+    }
+    editor.run()?;
+    if let Some(mut output) = output {
+        // The terminal has to be restored before anything else shows up on it.
+        editor.into_document().write_to(&mut output)?;
+    }
+    Ok(())
+}
+
+// With --stdout, the buffer has to get to wherever stdout points once the
+// editor quits. termion draws on stdout, so if that isn't the terminal, it
+// is kept aside and the terminal takes its place.
+fn take_stdout() -> Result<Box<dyn Write>, Error> {
+    if termion::is_tty(&io::stdout()) {
+        return Ok(Box::new(io::stdout()));
+    }
+    let tty = termion::get_tty()?;
+    // SAFETY: dup and dup2 only work on file descriptors, and the duplicate
+    // of stdout is owned by nothing but the File made from it.
+    unsafe {
+        let saved = libc::dup(1);
+        if saved < 0 || libc::dup2(tty.as_raw_fd(), 1) < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(Box::new(File::from_raw_fd(saved)))
+    }
+}
+
+fn open(path: &str, encoding: &'static Encoding) -> Result<Document, Error> {
+    match Document::open_with_encoding(path, encoding) {
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(Document::new_file(path, encoding)),
+        result => result.map_err(|error| Error::new(error.kind(), format!("{}: {}", path, error))),
+    }
 }
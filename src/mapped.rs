@@ -0,0 +1,165 @@
+use crate::Row;
+use memchr::memmem;
+use memmap2::Mmap;
+use std::cell::OnceCell;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Error;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+const CHUNK_LINES: usize = 4096;
+
+// A run of lines of a memory-mapped file. Lines are only decoded into rows
+// when something asks for them.
+pub struct Chunk {
+    bytes: Arc<Mmap>,
+    // Start offset of every line, followed by the end of the last one.
+    starts: Vec<usize>,
+    rows: OnceCell<Vec<OnceCell<Row>>>,
+}
+
+impl Chunk {
+    pub fn len(&self) -> usize {
+        self.starts.len().saturating_sub(1)
+    }
+
+    pub fn line(&self, index: usize) -> &[u8] {
+        let start = self.starts.get(index).copied().unwrap_or(0);
+        let end = self.starts.get(index.saturating_add(1)).copied().unwrap_or(start);
+        let line = self.bytes.get(start..end).unwrap_or(&[]);
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_suffix(b"\r").unwrap_or(line)
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.len()).map(move |index| self.line(index))
+    }
+
+    pub fn row(&self, index: usize) -> Option<&Row> {
+        if index >= self.len() {
+            return None;
+        }
+        let rows = self
+            .rows
+            .get_or_init(|| (0..self.len()).map(|_| OnceCell::new()).collect());
+        let row = rows.get(index)?;
+        Some(row.get_or_init(|| decode(self.line(index))))
+    }
+
+    pub fn into_rows(mut self) -> Vec<Row> {
+        let mut decoded = self.rows.take().unwrap_or_default().into_iter();
+        (0..self.len())
+            .map(|index| {
+                decoded
+                    .next()
+                    .and_then(OnceCell::into_inner)
+                    .unwrap_or_else(|| decode(self.line(index)))
+            })
+            .collect()
+    }
+
+    // Index of the first line containing `query`, searched in the raw bytes
+    // so the chunk doesn't have to be decoded.
+    pub fn find(&self, query: &str) -> Option<usize> {
+        let start = self.starts.first().copied()?;
+        let end = self.starts.last().copied()?;
+        let offset = memmem::find(self.bytes.get(start..end)?, query.as_bytes())?;
+        let offset = start.saturating_add(offset);
+        let line = self.starts.partition_point(|&line_start| line_start <= offset);
+        Some(line.saturating_sub(1))
+    }
+}
+
+fn decode(line: &[u8]) -> Row {
+    Row::from(&String::from_utf8_lossy(line)[..])
+}
+
+enum Progress {
+    Lines(Vec<usize>),
+    Done(u64),
+}
+
+// Scans a memory-mapped file for line breaks on a background thread and
+// hands out the lines found so far as chunks.
+pub struct Indexer {
+    bytes: Arc<Mmap>,
+    receiver: Receiver<Progress>,
+    indexed: usize,
+}
+
+impl Indexer {
+    pub fn start(file: &File) -> Result<Self, Error> {
+        // SAFETY: the map is only read. Like any other program reading the
+        // file, we see whatever someone else writes to it meanwhile.
+        let bytes = Arc::new(unsafe { Mmap::map(file)? });
+        let (sender, receiver) = mpsc::channel();
+        let scan = Arc::clone(&bytes);
+        thread::spawn(move || {
+            let mut hasher = DefaultHasher::new();
+            let mut starts = vec![0];
+            let mut hashed = 0;
+            for newline in memchr::memchr_iter(b'\n', &scan) {
+                starts.push(newline.saturating_add(1));
+                if starts.len() > CHUNK_LINES {
+                    let next = starts.last().copied().unwrap_or(0);
+                    hasher.write(scan.get(hashed..next).unwrap_or(&[]));
+                    hashed = next;
+                    if sender.send(Progress::Lines(starts)).is_err() {
+                        return;
+                    }
+                    starts = vec![next];
+                }
+            }
+            if starts.last().copied().unwrap_or(0) < scan.len() {
+                starts.push(scan.len());
+            }
+            hasher.write(scan.get(hashed..).unwrap_or(&[]));
+            if starts.len() > 1 {
+                let _ = sender.send(Progress::Lines(starts));
+            }
+            let _ = sender.send(Progress::Done(hasher.finish()));
+        });
+        Ok(Self {
+            bytes,
+            receiver,
+            indexed: 0,
+        })
+    }
+
+    // The whole mapped file.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // Chunks indexed since the last call, and the hash of the whole file
+    // once scanning is complete.
+    pub fn poll(&mut self) -> (Vec<Chunk>, Option<u64>) {
+        let mut chunks = Vec::new();
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Progress::Lines(starts)) => {
+                    self.indexed = starts.last().copied().unwrap_or(self.indexed);
+                    chunks.push(Chunk {
+                        bytes: Arc::clone(&self.bytes),
+                        starts,
+                        rows: OnceCell::new(),
+                    });
+                }
+                Ok(Progress::Done(hash)) => return (chunks, Some(hash)),
+                Err(TryRecvError::Empty) => return (chunks, None),
+                Err(TryRecvError::Disconnected) => return (chunks, Some(0)),
+            }
+        }
+    }
+
+    #[allow(clippy::integer_arithmetic, clippy::integer_division, clippy::cast_possible_truncation)]
+    pub fn percent(&self) -> u8 {
+        if self.bytes.is_empty() {
+            return 100;
+        }
+        (self.indexed.saturating_mul(100) / self.bytes.len()) as u8
+    }
+}
@@ -0,0 +1,127 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+// Comment and quote markers that stay in front of reflowed text, longest
+// first so that `///` isn't read as `//` followed by text.
+const MARKERS: &[&str] = &["///", "//!", "//", "#", ">", "--", ";"];
+
+// The indentation and markers a line starts with, and the spaces after
+// them.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+pub fn prefix(line: &str) -> &str {
+    let mut rest = line.trim_start();
+    while let Some(marker) = MARKERS.iter().find(|&&marker| rest.starts_with(marker)) {
+        rest = rest[marker.len()..].trim_start();
+    }
+    &line[..line.len() - rest.len()]
+}
+
+// What starts the lines that continue a line starting with `prefix`: the
+// same, with a space after a marker.
+pub fn continuation(prefix: &str) -> String {
+    if prefix.trim().is_empty() || prefix.ends_with(char::is_whitespace) {
+        prefix.to_string()
+    } else {
+        format!("{} ", prefix)
+    }
+}
+
+// Whether a line has nothing but its prefix, which ends a paragraph.
+pub fn is_blank(line: &str) -> bool {
+    line.len() == prefix(line).len()
+}
+
+// Fills each paragraph of `lines` with as many words per line as fit in
+// `width` columns. Paragraphs end at blank lines and where the markers
+// change, and keep the prefix of their first line.
+pub fn reflow(lines: &[String], width: usize) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut lines = lines.iter().map(String::as_str).peekable();
+    while let Some(line) = lines.next() {
+        if is_blank(line) {
+            result.push(line.to_string());
+            continue;
+        }
+        paragraph.push(line);
+        let next = lines.peek().copied().unwrap_or("");
+        if is_blank(next) || prefix(next).trim() != prefix(line).trim() {
+            result.extend(fill(&paragraph, width));
+            paragraph.clear();
+        }
+    }
+    result
+}
+
+// The words of `paragraph` in lines of at most `width` columns, each
+// starting like the first.
+fn fill(paragraph: &[&str], width: usize) -> Vec<String> {
+    let lead = continuation(paragraph.first().map_or("", |first| prefix(first)));
+    let room = width.saturating_sub(lead.graphemes(true).count()).max(1);
+    let words = paragraph
+        .iter()
+        .flat_map(|line| line.get(prefix(line).len()..).unwrap_or("").split_whitespace());
+    let mut result = Vec::new();
+    let mut current = String::new();
+    for word in words {
+        let len = current.graphemes(true).count();
+        if len > 0 && len.saturating_add(1).saturating_add(word.graphemes(true).count()) > room {
+            result.push(format!("{}{}", lead, current));
+            current.clear();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    result.push(format!("{}{}", lead, current));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<String> {
+        text.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(prefix("    // text"), "    // ");
+        assert_eq!(prefix("/// docs"), "/// ");
+        assert_eq!(prefix("> > quoted"), "> > ");
+        assert_eq!(prefix("plain"), "");
+        assert!(is_blank("  #  "));
+    }
+
+    #[test]
+    fn reflows_paragraphs_keeping_prefixes() {
+        let text = lines(&[
+            "    // one two three four",
+            "    // five six",
+            "    //",
+            "    // seven eight nine ten eleven",
+        ]);
+        assert_eq!(
+            reflow(&text, 24),
+            lines(&[
+                "    // one two three",
+                "    // four five six",
+                "    //",
+                "    // seven eight nine",
+                "    // ten eleven",
+            ])
+        );
+    }
+
+    #[test]
+    fn paragraphs_end_where_the_markers_change() {
+        let text = lines(&["# a", "b", "c", "// d", "// e"]);
+        assert_eq!(reflow(&text, 40), lines(&["# a", "b c", "// d e"]));
+    }
+
+    #[test]
+    fn long_words_get_lines_of_their_own() {
+        assert_eq!(reflow(&lines(&["a verylongword b"]), 5), lines(&["a", "verylongword", "b"]));
+    }
+}
@@ -0,0 +1,565 @@
+use crate::mapped::Chunk;
+use crate::Row;
+use std::iter::FromIterator;
+use std::ops::Range;
+use std::slice;
+
+const MAX_LEAF: usize = 128;
+const MIN_LEAF: usize = MAX_LEAF / 4;
+const MAX_CHILDREN: usize = 16;
+const MIN_CHILDREN: usize = MAX_CHILDREN / 4;
+
+enum Children {
+    Leaf(Vec<Row>),
+    // Lines of a large file that haven't been edited yet. Turned into a
+    // `Leaf` as soon as anything changes in them.
+    Lazy(Chunk),
+    Branch(Vec<Node>),
+}
+
+pub enum Segment<'a> {
+    Rows(&'a [Row]),
+    Lines(&'a Chunk),
+}
+
+// Every node caches the number of rows below it, so finding, inserting and
+// removing a row only walks one path from the root instead of shifting every
+// following row like a `Vec<Row>` would.
+struct Node {
+    rows: usize,
+    children: Children,
+}
+
+impl Node {
+    fn leaf(rows: Vec<Row>) -> Self {
+        Self {
+            rows: rows.len(),
+            children: Children::Leaf(rows),
+        }
+    }
+
+    fn branch(children: Vec<Self>) -> Self {
+        Self {
+            rows: children.iter().map(|child| child.rows).sum(),
+            children: Children::Branch(children),
+        }
+    }
+
+    fn lazy(chunk: Chunk) -> Self {
+        Self {
+            rows: chunk.len(),
+            children: Children::Lazy(chunk),
+        }
+    }
+
+    fn is_lazy(&self) -> bool {
+        matches!(self.children, Children::Lazy(_))
+    }
+
+    fn is_branch(&self) -> bool {
+        matches!(self.children, Children::Branch(_))
+    }
+
+    // Decodes a lazy chunk so its rows can be changed.
+    fn materialize(&mut self) {
+        if self.is_lazy() {
+            let children = std::mem::replace(&mut self.children, Children::Leaf(Vec::new()));
+            if let Children::Lazy(chunk) = children {
+                self.children = Children::Leaf(chunk.into_rows());
+            }
+        }
+    }
+
+    fn width(&self) -> usize {
+        match &self.children {
+            Children::Leaf(rows) => rows.len(),
+            Children::Lazy(chunk) => chunk.len(),
+            Children::Branch(children) => children.len(),
+        }
+    }
+
+    fn is_overfull(&self) -> bool {
+        match &self.children {
+            Children::Leaf(rows) => rows.len() > MAX_LEAF,
+            Children::Lazy(_) => false,
+            Children::Branch(children) => children.len() > MAX_CHILDREN,
+        }
+    }
+
+    fn is_underfull(&self) -> bool {
+        match &self.children {
+            Children::Leaf(rows) => rows.len() < MIN_LEAF,
+            Children::Lazy(_) => false,
+            Children::Branch(children) => children.len() < MIN_CHILDREN,
+        }
+    }
+
+    // Moves the upper half of this node's children into a new sibling.
+    #[allow(clippy::integer_division)]
+    fn split(&mut self) -> Self {
+        self.materialize();
+        let at = self.width() / 2;
+        let sibling = match &mut self.children {
+            Children::Leaf(rows) => Self::leaf(rows.split_off(at)),
+            Children::Branch(children) => Self::branch(children.split_off(at)),
+            Children::Lazy(_) => Self::leaf(Vec::new()),
+        };
+        self.rows = self.rows.saturating_sub(sibling.rows);
+        sibling
+    }
+
+    // Appends the children of a sibling at the same depth.
+    fn absorb(&mut self, mut other: Self) {
+        self.materialize();
+        other.materialize();
+        self.rows = self.rows.saturating_add(other.rows);
+        match (&mut self.children, other.children) {
+            (Children::Leaf(rows), Children::Leaf(more)) => rows.extend(more),
+            (Children::Branch(children), Children::Branch(more)) => children.extend(more),
+            _ => unreachable!("siblings are always at the same depth"),
+        }
+    }
+
+    fn get(&self, mut index: usize) -> Option<&Row> {
+        match &self.children {
+            Children::Leaf(rows) => rows.get(index),
+            Children::Lazy(chunk) => chunk.row(index),
+            Children::Branch(children) => {
+                for child in children {
+                    if index < child.rows {
+                        return child.get(index);
+                    }
+                    index = index.saturating_sub(child.rows);
+                }
+                None
+            }
+        }
+    }
+
+    fn get_mut(&mut self, mut index: usize) -> Option<&mut Row> {
+        self.materialize();
+        match &mut self.children {
+            Children::Leaf(rows) => rows.get_mut(index),
+            Children::Lazy(_) => None,
+            Children::Branch(children) => {
+                for child in children {
+                    if index < child.rows {
+                        return child.get_mut(index);
+                    }
+                    index = index.saturating_sub(child.rows);
+                }
+                None
+            }
+        }
+    }
+
+    // Inserts `row` before `index`, returning a new right sibling if this
+    // node had to be split.
+    fn insert(&mut self, mut index: usize, row: Row) -> Option<Self> {
+        self.rows = self.rows.saturating_add(1);
+        self.materialize();
+        match &mut self.children {
+            Children::Leaf(rows) => rows.insert(index, row),
+            Children::Lazy(_) => (),
+            Children::Branch(children) => {
+                let last = children.len().saturating_sub(1);
+                let mut child_idx = last;
+                for (idx, child) in children.iter().enumerate() {
+                    if index <= child.rows {
+                        child_idx = idx;
+                        break;
+                    }
+                    index = index.saturating_sub(child.rows);
+                }
+                let child = children.get_mut(child_idx)?;
+                if let Some(sibling) = child.insert(index, row) {
+                    children.insert(child_idx.saturating_add(1), sibling);
+                }
+            }
+        }
+        if self.is_overfull() {
+            Some(self.split())
+        } else {
+            None
+        }
+    }
+
+    fn remove_range(&mut self, start: usize, end: usize) {
+        self.materialize();
+        match &mut self.children {
+            Children::Leaf(rows) => {
+                rows.drain(start..end);
+            }
+            Children::Lazy(_) => (),
+            Children::Branch(children) => {
+                let mut offset: usize = 0;
+                for child in children.iter_mut() {
+                    let child_start = offset;
+                    let child_end = offset.saturating_add(child.rows);
+                    offset = child_end;
+                    if child_end <= start || end <= child_start {
+                        continue;
+                    }
+                    let from = start.saturating_sub(child_start);
+                    let to = end.min(child_end).saturating_sub(child_start);
+                    if from == 0 && to == child.rows {
+                        child.rows = 0;
+                    } else {
+                        child.remove_range(from, to);
+                    }
+                }
+                children.retain(|child| child.rows > 0);
+                rebalance(children);
+            }
+        }
+        self.rows = self.rows.saturating_sub(end.saturating_sub(start));
+    }
+
+    // Appends a leaf after the last one, returning a new right sibling if
+    // this node had to be split.
+    fn push_leaf(&mut self, leaf: Self) -> Option<Self> {
+        self.rows = self.rows.saturating_add(leaf.rows);
+        if let Children::Branch(children) = &mut self.children {
+            match children.last_mut() {
+                Some(last) if last.is_branch() => {
+                    if let Some(sibling) = last.push_leaf(leaf) {
+                        children.push(sibling);
+                    }
+                }
+                _ => children.push(leaf),
+            }
+        }
+        if self.is_overfull() {
+            Some(self.split())
+        } else {
+            None
+        }
+    }
+}
+
+// Merges underfull children into their neighbours and splits anything that
+// grew too large doing so.
+fn rebalance(children: &mut Vec<Node>) {
+    let mut idx: usize = 0;
+    while idx.saturating_add(1) < children.len() {
+        let next = idx.saturating_add(1);
+        // Lazy chunks are left alone, merging would force decoding them.
+        let underfull = children
+            .get(idx)
+            .zip(children.get(next))
+            .map_or(false, |(a, b)| {
+                (a.is_underfull() || b.is_underfull()) && !a.is_lazy() && !b.is_lazy()
+            });
+        if !underfull {
+            idx = next;
+            continue;
+        }
+        let sibling = children.remove(next);
+        if let Some(child) = children.get_mut(idx) {
+            child.absorb(sibling);
+            if child.is_overfull() {
+                let split = child.split();
+                children.insert(next, split);
+                idx = next;
+            }
+        }
+    }
+}
+
+pub struct Rope {
+    root: Node,
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self {
+            root: Node::leaf(Vec::new()),
+        }
+    }
+}
+
+impl FromIterator<Row> for Rope {
+    fn from_iter<I: IntoIterator<Item = Row>>(iter: I) -> Self {
+        let mut level = Vec::new();
+        let mut leaf = Vec::with_capacity(MAX_LEAF);
+        for row in iter {
+            leaf.push(row);
+            if leaf.len() == MAX_LEAF {
+                level.push(Node::leaf(leaf));
+                leaf = Vec::with_capacity(MAX_LEAF);
+            }
+        }
+        if !leaf.is_empty() || level.is_empty() {
+            level.push(Node::leaf(leaf));
+        }
+        while level.len() > 1 {
+            let mut parents = Vec::new();
+            let mut nodes = level.into_iter().peekable();
+            while nodes.peek().is_some() {
+                parents.push(Node::branch(nodes.by_ref().take(MAX_CHILDREN).collect()));
+            }
+            level = parents;
+        }
+        Self {
+            root: level.pop().unwrap_or_else(|| Node::leaf(Vec::new())),
+        }
+    }
+}
+
+impl Rope {
+    pub fn len(&self) -> usize {
+        self.root.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.rows == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Row> {
+        self.root.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Row> {
+        self.root.get_mut(index)
+    }
+
+    pub fn push(&mut self, row: Row) {
+        self.insert(self.len(), row);
+    }
+
+    pub fn insert(&mut self, index: usize, row: Row) {
+        if index > self.len() {
+            return;
+        }
+        if let Some(sibling) = self.root.insert(index, row) {
+            let root = std::mem::replace(&mut self.root, Node::leaf(Vec::new()));
+            self.root = Node::branch(vec![root, sibling]);
+        }
+    }
+
+    pub fn push_chunk(&mut self, chunk: Chunk) {
+        let leaf = Node::lazy(chunk);
+        if self.root.is_branch() {
+            if let Some(sibling) = self.root.push_leaf(leaf) {
+                let root = std::mem::replace(&mut self.root, Node::leaf(Vec::new()));
+                self.root = Node::branch(vec![root, sibling]);
+            }
+        } else if self.root.rows == 0 {
+            self.root = leaf;
+        } else {
+            let root = std::mem::replace(&mut self.root, Node::leaf(Vec::new()));
+            self.root = Node::branch(vec![root, leaf]);
+        }
+    }
+
+    pub fn insert_rows<I: IntoIterator<Item = Row>>(&mut self, index: usize, rows: I) {
+        for (offset, row) in rows.into_iter().enumerate() {
+            self.insert(index.saturating_add(offset), row);
+        }
+    }
+
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.len());
+        if range.start >= end {
+            return;
+        }
+        self.root.remove_range(range.start, end);
+        // Drop levels that are left with a single child.
+        loop {
+            match &mut self.root.children {
+                Children::Branch(children) if children.len() <= 1 => {
+                    self.root = children.pop().unwrap_or_else(|| Node::leaf(Vec::new()));
+                }
+                _ => break,
+            }
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.iter_at(0)
+    }
+
+    // Iterates the rows starting at `index`.
+    pub fn iter_at(&self, mut index: usize) -> Iter<'_> {
+        let mut stack = Vec::new();
+        let mut node = &self.root;
+        while let Children::Branch(children) = &node.children {
+            let mut nodes = children.iter();
+            node = loop {
+                match nodes.next() {
+                    Some(child) if index < child.rows => break child,
+                    Some(child) => index = index.saturating_sub(child.rows),
+                    None => {
+                        return Iter {
+                            leaves: Leaves { stack: Vec::new() },
+                            leaf: None,
+                            index: 0,
+                        }
+                    }
+                }
+            };
+            stack.push(nodes);
+        }
+        Iter {
+            leaves: Leaves { stack },
+            leaf: Some(node),
+            index,
+        }
+    }
+
+    // The rows in order, with lazy chunks handed out undecoded.
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'_>> {
+        Leaves {
+            stack: vec![slice::from_ref(&self.root).iter()],
+        }
+        .filter_map(|leaf| match &leaf.children {
+            Children::Leaf(rows) => Some(Segment::Rows(rows)),
+            Children::Lazy(chunk) => Some(Segment::Lines(chunk)),
+            Children::Branch(_) => None,
+        })
+    }
+}
+
+struct Leaves<'a> {
+    stack: Vec<slice::Iter<'a, Node>>,
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<&'a Node> {
+        loop {
+            let node = loop {
+                let nodes = self.stack.last_mut()?;
+                if let Some(node) = nodes.next() {
+                    break node;
+                }
+                self.stack.pop();
+            };
+            if let Children::Branch(children) = &node.children {
+                self.stack.push(children.iter());
+            } else {
+                return Some(node);
+            }
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    leaves: Leaves<'a>,
+    leaf: Option<&'a Node>,
+    index: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Row;
+
+    fn next(&mut self) -> Option<&'a Row> {
+        loop {
+            if let Some(row) = self.leaf?.get(self.index) {
+                self.index = self.index.saturating_add(1);
+                return Some(row);
+            }
+            self.leaf = self.leaves.next();
+            self.index = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, usize),
+        Remove(usize, usize),
+        Get(usize),
+    }
+
+    // Enough rows to split leaves and branches, and removals long enough to
+    // empty whole branches.
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..6000_usize, 1..600_usize).prop_map(|(at, count)| Op::Insert(at, count)),
+            (0..6000_usize, 1..3000_usize).prop_map(|(at, count)| Op::Remove(at, count)),
+            (0..6000_usize).prop_map(Op::Get),
+        ]
+    }
+
+    // Checks the cached row counts and node sizes below `node`, returning
+    // its depth.
+    fn check(node: &Node, root: bool) -> Result<usize, TestCaseError> {
+        match &node.children {
+            Children::Leaf(rows) => {
+                prop_assert_eq!(node.rows, rows.len());
+                prop_assert!(rows.len() <= MAX_LEAF);
+                prop_assert!(root || !rows.is_empty());
+                Ok(0)
+            }
+            Children::Lazy(chunk) => {
+                prop_assert_eq!(node.rows, chunk.len());
+                Ok(0)
+            }
+            Children::Branch(children) => {
+                prop_assert!(children.len() <= MAX_CHILDREN);
+                prop_assert!(!root || children.len() > 1);
+                prop_assert_eq!(node.rows, children.iter().map(|child| child.rows).sum::<usize>());
+                let mut depths = Vec::new();
+                for child in children {
+                    depths.push(check(child, false)?);
+                }
+                prop_assert!(depths.windows(2).all(|pair| pair[0] == pair[1]));
+                Ok(depths.first().map_or(0, |depth| depth + 1))
+            }
+        }
+    }
+
+    fn lines<'a, I: Iterator<Item = &'a Row>>(rows: I) -> Vec<&'a str> {
+        rows.map(Row::as_str).collect()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn edits_match_a_vec_and_keep_the_tree_balanced(
+            start in 0..5000_usize,
+            ops in prop::collection::vec(op(), 0..20),
+        ) {
+            let mut next = 0_usize;
+            let mut row = || {
+                next += 1;
+                Row::from(&next.to_string()[..])
+            };
+            let mut model: Vec<Row> = (0..start).map(|_| row()).collect();
+            let mut rope: Rope = model.iter().map(|row| Row::from(row.as_str())).collect();
+            check(&rope.root, true)?;
+            for op in ops {
+                match op {
+                    Op::Insert(at, count) => {
+                        let at = at % (model.len() + 1);
+                        let rows: Vec<Row> = (0..count).map(|_| row()).collect();
+                        rope.insert_rows(at, rows.iter().map(|row| Row::from(row.as_str())));
+                        model.splice(at..at, rows);
+                    }
+                    Op::Remove(at, count) => {
+                        let at = at % (model.len() + 1);
+                        let end = (at + count).min(model.len());
+                        rope.remove_range(at..at + count);
+                        model.drain(at..end);
+                    }
+                    Op::Get(at) => {
+                        let at = at % (model.len() + 1);
+                        prop_assert_eq!(rope.get(at).map(Row::as_str), model.get(at).map(Row::as_str));
+                        prop_assert_eq!(lines(rope.iter_at(at)), lines(model[at..].iter()));
+                    }
+                }
+                check(&rope.root, true)?;
+                prop_assert_eq!(rope.len(), model.len());
+                prop_assert_eq!(lines(rope.iter()), lines(model.iter()));
+            }
+        }
+    }
+}
@@ -1,128 +1,357 @@
-use std::{cmp, slice::SliceIndex};
+use std::cmp;
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::Position;
-
 #[derive(Default)]
 pub struct Row {
     string: String,
-    len: usize,
+    // Byte offset of the start of every grapheme, kept up to date on edits so
+    // that grapheme indices map to bytes without re-segmenting the row.
+    offsets: Vec<usize>,
+    // Byte offset of every tab, the only graphemes wider than one column,
+    // so screen columns are found without walking the whole row.
+    tabs: Vec<usize>,
 }
 
 impl From<&str> for Row {
     fn from(slice: &str) -> Self {
-        Self{
+        Self {
             string: String::from(slice),
-            len: slice.graphemes(true).count(),
+            offsets: slice.grapheme_indices(true).map(|(idx, _)| idx).collect(),
+            tabs: tabs(slice, 0).collect(),
         }
     }
 }
 
+// Byte offsets of the tabs in `text`, which starts at byte `start`. A tab
+// is always a grapheme of its own.
+fn tabs(text: &str, start: usize) -> impl Iterator<Item = usize> + '_ {
+    memchr::memchr_iter(b'\t', text.as_bytes()).map(move |idx| start.saturating_add(idx))
+}
+
+// Screen columns `grapheme` takes up when it starts at `column`: a tab
+// reaches the next multiple of `tab_width`, anything else takes one.
+#[allow(clippy::integer_arithmetic)]
+pub fn width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = tab_width.max(1);
+        tab_width.saturating_sub(column % tab_width)
+    } else {
+        1
+    }
+}
+
 impl Row {
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let end = cmp::min(end, self.string.len());
-        let start = cmp::min(start, end);
-        let mut result = String::new();
-        #[allow(clippy::integer_arithmetic)]
-        for grapheme in self.string[..]
-            .graphemes(true)
-            .skip(start)
-            .take(end - start)
-        {
+    // The graphemes `start..end`, with tabs expanded to spaces as if the
+    // first were drawn at screen column `column`.
+    pub fn render(&self, start: usize, end: usize, column: usize, tab_width: usize) -> String {
+        let slice = self.slice(start, end);
+        if !slice.contains('\t') {
+            return slice.to_string();
+        }
+        let mut rendered = String::with_capacity(slice.len());
+        let mut column = column;
+        for grapheme in slice.graphemes(true) {
+            let width = width(grapheme, column, tab_width);
             if grapheme == "\t" {
-                result.push_str(" ");
+                rendered.extend(std::iter::repeat(' ').take(width));
             } else {
-                result.push_str(grapheme);
+                rendered.push_str(grapheme);
+            }
+            column = column.saturating_add(width);
+        }
+        rendered
+    }
+
+    // The screen column grapheme `at` starts at.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn column(&self, at: usize, tab_width: usize) -> usize {
+        let at = cmp::min(at, self.len());
+        // The column and grapheme right after the last tab so far.
+        let (mut column, mut after) = (0, 0);
+        for &byte in &self.tabs {
+            let x = self.index(byte);
+            if x >= at {
+                break;
+            }
+            column += x - after;
+            column += width("\t", column, tab_width);
+            after = x + 1;
+        }
+        column + (at - after)
+    }
+
+    // The grapheme drawn over screen column `column`, or the end of the row.
+    #[allow(clippy::integer_arithmetic)]
+    pub fn at_column(&self, column: usize, tab_width: usize) -> usize {
+        let (mut start, mut after) = (0, 0);
+        for &byte in &self.tabs {
+            let x = self.index(byte);
+            let tab = start + (x - after);
+            if column < tab {
+                break;
             }
+            start = tab + width("\t", tab, tab_width);
+            if column < start {
+                return x;
+            }
+            after = x + 1;
         }
-        result
+        cmp::min(after + (column - start), self.len())
     }
 
     pub fn len(&self) -> usize {
-        self.len
+        self.offsets.len()
+    }
+
+    // Where the whitespace at the end of the row starts.
+    pub fn trailing_whitespace(&self) -> usize {
+        self.index(self.string.trim_end().len())
     }
 
     pub fn is_empty(&self) -> bool {
-        self.len == 0
+        self.offsets.is_empty()
     }
 
     pub fn insert(&mut self, at: usize, c: char) {
-        if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
-            return;
-        }
-        let mut result: String = String::new();
-        let mut lenght = 0;
-        for (idx, grapheme) in self.string[..].graphemes(true).enumerate() {
-            lenght += 1;
-            if idx == at {
-                lenght += 1;
-                result.push(c);
-            }
-            result.push_str(grapheme);
-        }
-        self.len = lenght;
-        self.string = result;
+        let byte = self.byte(at);
+        self.splice(byte, byte, c.encode_utf8(&mut [0; 4]));
     }
 
-    #[allow(clippy::integer_arithmetic)]
     pub fn delete(&mut self, at: usize) {
         if at >= self.len() {
             return;
         }
-        let mut result: String = String::new();
-        let mut lenght = 0;
-        for (idx, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if idx != at {
-                lenght += 1;
-                result.push_str(grapheme);
-            }
-        }
-        self.len = lenght;
-        self.string = result;
+        self.splice(self.byte(at), self.byte(at.saturating_add(1)), "");
+    }
+
+    pub fn insert_str(&mut self, at: usize, string: &str) {
+        let byte = self.byte(at);
+        self.splice(byte, byte, string);
     }
 
     pub fn append(&mut self, new: &Self) {
-        self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len();
+        let end = self.string.len();
+        self.splice(end, end, &new.string);
     }
 
     pub fn split(&mut self, at: usize) -> Self {
-        let mut row: String = String::new();
-        let mut lenght = 0;
-        let mut splitted_row: String = String::new();
-        let mut splitted_len = 0;
-        for (idx, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if idx < at {
-                lenght += 1;
-                row.push_str(grapheme);
-            } else {
-                splitted_len += 1;
-                splitted_row.push_str(grapheme);
-            }
-        }
-        self.string = row;
-        self.len = lenght;
-        Self {
-            string: splitted_row,
-            len: splitted_len,
-        }
+        let at = cmp::min(at, self.len());
+        let byte = self.byte(at);
+        let string = self.string.split_off(byte);
+        let offsets = self
+            .offsets
+            .split_off(at)
+            .into_iter()
+            .map(|offset| offset.saturating_sub(byte))
+            .collect();
+        let first = self.tabs.partition_point(|&tab| tab < byte);
+        let tabs = self.tabs.split_off(first).into_iter().map(|tab| tab.saturating_sub(byte)).collect();
+        Self { string, offsets, tabs }
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         self.string.as_bytes()
     }
 
+    pub fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    pub fn slice(&self, start: usize, end: usize) -> &str {
+        if end <= start {
+            return "";
+        }
+        self.string
+            .get(self.byte(start)..self.byte(end))
+            .unwrap_or("")
+    }
+
     pub fn find(&self, query: &str) -> Option<usize> {
-        let matching_byte_idx = self.string.find(query);
-        if let Some(matching_byte_idx) = matching_byte_idx {
-            for (grapheme_idx, (byte_idx, _)) in self.string[..].grapheme_indices(true).enumerate() {
-                if matching_byte_idx == byte_idx {
-                    return Some(grapheme_idx);
+        let matching_byte_idx = self.string.find(query)?;
+        self.offsets.binary_search(&matching_byte_idx).ok()
+    }
+
+    // Where the first word ending after `at` ends.
+    pub fn word_end_after(&self, at: usize) -> Option<usize> {
+        self.words().map(|(_, end)| end).find(|&end| end > at)
+    }
+
+    // Where the last word starting before `at` starts.
+    pub fn word_start_before(&self, at: usize) -> Option<usize> {
+        self.words()
+            .map(|(start, _)| start)
+            .take_while(|&start| start < at)
+            .last()
+    }
+
+    // Grapheme ranges of the words in the row: the Unicode word-bound
+    // segments with a letter or digit in them.
+    fn words(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.string
+            .split_word_bound_indices()
+            .filter(|(_, word)| word.chars().any(char::is_alphanumeric))
+            .map(move |(byte, word)| (self.index(byte), self.index(byte.saturating_add(word.len()))))
+    }
+
+    // Grapheme index of the byte offset `byte`.
+    fn index(&self, byte: usize) -> usize {
+        self.offsets.partition_point(|&offset| offset < byte)
+    }
+
+    // Byte offset of the grapheme at `at`, or the end of the row.
+    fn byte(&self, at: usize) -> usize {
+        self.offsets.get(at).copied().unwrap_or(self.string.len())
+    }
+
+    // Replaces the bytes `start..end` with `text` and re-segments only the
+    // graphemes around the edit, until the boundaries line up with the old
+    // ones again.
+    fn splice(&mut self, start: usize, end: usize, text: &str) {
+        // The grapheme before the edit may absorb or shed characters.
+        let first = self
+            .offsets
+            .partition_point(|&offset| offset < start)
+            .saturating_sub(1);
+        let from = cmp::min(self.offsets.get(first).copied().unwrap_or(start), start);
+        let edit_end = start.saturating_add(text.len());
+        let shift = |offset: usize| offset.saturating_sub(end).saturating_add(edit_end);
+        self.string.replace_range(start..end, text);
+
+        let mut old = self.offsets.partition_point(|&offset| offset < end);
+        let mut resume = self.offsets.len();
+        let mut fresh = Vec::new();
+        for (idx, _) in self.string.get(from..).unwrap_or("").grapheme_indices(true) {
+            let offset = from.saturating_add(idx);
+            if offset >= edit_end {
+                while self.offsets.get(old).map_or(false, |&o| shift(o) < offset) {
+                    old = old.saturating_add(1);
                 }
+                if self.offsets.get(old).map_or(false, |&o| shift(o) == offset) {
+                    resume = old;
+                    break;
+                }
+            }
+            fresh.push(offset);
+        }
+        let kept = first.saturating_add(fresh.len());
+        self.offsets.splice(first..resume, fresh);
+        if let Some(tail) = self.offsets.get_mut(kept..) {
+            for offset in tail {
+                *offset = shift(*offset);
             }
         }
-        None
+        let first = self.tabs.partition_point(|&tab| tab < start);
+        let last = self.tabs.partition_point(|&tab| tab < end);
+        let after: Vec<usize> = self.tabs.get(last..).unwrap_or(&[]).iter().map(|&tab| shift(tab)).collect();
+        let inserted: Vec<usize> = tabs(text, start).collect();
+        self.tabs.truncate(first);
+        self.tabs.extend(inserted);
+        self.tabs.extend(after);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Combining marks, joiners and regional indicators all change where
+    // grapheme boundaries fall around an edit.
+    fn text() -> impl Strategy<Value = String> {
+        prop::collection::vec(
+            prop_oneof![
+                Just("a"),
+                Just("e"),
+                Just("\u{301}"),
+                Just("\u{200d}"),
+                Just("👍"),
+                Just("🇩"),
+                Just("🇪"),
+                Just("\r"),
+                Just("\n"),
+                Just("\t"),
+            ],
+            0..6,
+        )
+        .prop_map(|parts| parts.concat())
+    }
+
+    fn graphemes(string: &str) -> Vec<&str> {
+        string.graphemes(true).collect()
+    }
+
+    fn check(row: &Row, model: &str) -> Result<(), TestCaseError> {
+        prop_assert_eq!(row.as_str(), model);
+        prop_assert_eq!(row.len(), graphemes(model).len());
+        let mut column = 0;
+        for (at, grapheme) in graphemes(model).into_iter().enumerate() {
+            prop_assert_eq!(row.slice(at, at + 1), grapheme);
+            prop_assert_eq!(row.column(at, 4), column);
+            prop_assert_eq!(row.at_column(column, 4), at);
+            column += width(grapheme, column, 4);
+        }
+        prop_assert_eq!(row.trailing_whitespace(), graphemes(model.trim_end()).len());
+        prop_assert_eq!(row.column(row.len(), 4), column);
+        prop_assert_eq!(row.at_column(column, 4), row.len());
+        Ok(())
+    }
+
+    #[test]
+    fn tabs_reach_the_next_stop() {
+        let row = Row::from("a\tbc\td");
+        assert_eq!(row.column(1, 4), 1);
+        assert_eq!(row.column(2, 4), 4);
+        assert_eq!(row.column(5, 4), 8);
+        assert_eq!(row.column(6, 4), 9);
+        assert_eq!(row.at_column(3, 4), 1);
+        assert_eq!(row.at_column(4, 4), 2);
+        assert_eq!(row.at_column(20, 4), 6);
+        assert_eq!(row.render(0, 6, 0, 4), "a   bc  d");
+        assert_eq!(row.render(1, 3, 3, 4), " b");
+    }
+
+    #[test]
+    fn word_boundaries() {
+        let row = Row::from("  let naïve_name = café.len();");
+        assert_eq!(row.word_end_after(0), Some(5));
+        assert_eq!(row.word_end_after(5), Some(16));
+        assert_eq!(row.word_start_before(16), Some(6));
+        assert_eq!(row.word_start_before(24), Some(19));
+        assert_eq!(row.word_end_after(27), None);
+        assert_eq!(row.word_start_before(2), None);
+    }
+
+    proptest! {
+        #[test]
+        fn insert_str_matches_resegmenting(start in text(), inserted in text(), at in 0..8_usize) {
+            let mut row = Row::from(&start[..]);
+            row.insert_str(at, &inserted);
+            let parts = graphemes(&start);
+            let at = at.min(parts.len());
+            check(&row, &format!("{}{}{}", parts[..at].concat(), inserted, parts[at..].concat()))?;
+        }
+
+        #[test]
+        fn delete_matches_resegmenting(start in text(), at in 0..8_usize) {
+            let mut row = Row::from(&start[..]);
+            row.delete(at);
+            let mut parts = graphemes(&start);
+            if at < parts.len() {
+                parts.remove(at);
+            }
+            check(&row, &parts.concat())?;
+        }
+
+        #[test]
+        fn split_and_append_round_trip(start in text(), at in 0..8_usize) {
+            let mut row = Row::from(&start[..]);
+            let tail = row.split(at);
+            let parts = graphemes(&start);
+            let at = at.min(parts.len());
+            check(&row, &parts[..at].concat())?;
+            check(&tail, &parts[at..].concat())?;
+            row.append(&tail);
+            check(&row, &start)?;
+        }
     }
 }
@@ -0,0 +1,33 @@
+use std::io::{Error, Write};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+// Runs `command` through `sh -c`, feeding it `input` on stdin when given.
+pub fn run(command: &str, input: Option<String>) -> Result<Output, Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // Feed stdin from another thread, the command may not read all of its
+    // input before its output pipe fills up.
+    let writer = match (child.stdin.take(), input) {
+        (Some(mut stdin), Some(input)) => {
+            Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        // A command that exits without reading its input closes the pipe
+        // early; that is for the command to judge, not an error here.
+        let _ = writer.join();
+    }
+    Ok(output)
+}
@@ -0,0 +1,105 @@
+use crate::Position;
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &str = "rustte-swap 1";
+
+pub struct Swap {
+    pub cursor: Position,
+    pub rows: Vec<String>,
+}
+
+impl Swap {
+    pub fn path_for(filename: &str) -> PathBuf {
+        Self::numbered_path_for(filename, 0)
+    }
+
+    // Swap paths beside the usual one, for sessions that leave an earlier
+    // session's swap file alone. Number 0 is the usual path.
+    pub fn numbered_path_for(filename: &str, number: usize) -> PathBuf {
+        let path = Path::new(filename);
+        let name = path
+            .file_name()
+            .map_or_else(|| filename.to_string(), |name| name.to_string_lossy().into_owned());
+        if number == 0 {
+            path.with_file_name(format!(".{}.rustte.swp", name))
+        } else {
+            path.with_file_name(format!(".{}.rustte.{}.swp", name, number))
+        }
+    }
+
+    // The first readable swap file an earlier session left for `filename`,
+    // and the number of its path. Numbered paths are looked at until one
+    // is missing.
+    pub fn find(filename: &str) -> (usize, Option<Self>) {
+        for number in 0.. {
+            let path = Self::numbered_path_for(filename, number);
+            if number > 0 && !path.exists() {
+                break;
+            }
+            // An unreadable swap file must not prevent opening the document.
+            if let Ok(Some(swap)) = Self::read(&path) {
+                return (number, Some(swap));
+            }
+        }
+        (0, None)
+    }
+
+    // The first numbered path for `filename` that no swap file is at.
+    pub fn free_number(filename: &str) -> usize {
+        (1..)
+            .find(|&number| !Self::numbered_path_for(filename, number).exists())
+            .unwrap_or(1)
+    }
+
+    pub fn read(path: &Path) -> Result<Option<Self>, Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let mut lines = contents.split('\n');
+        if lines.next() != Some(MAGIC) {
+            return Err(Error::new(ErrorKind::InvalidData, "not a rustte swap file"));
+        }
+        let mut cursor = Position::default();
+        if let Some(line) = lines.next() {
+            let mut coords = line.split(' ').map(str::parse::<usize>);
+            if let (Some(Ok(x)), Some(Ok(y))) = (coords.next(), coords.next()) {
+                cursor = Position { x, y };
+            }
+        }
+        let mut rows: Vec<String> = lines.map(String::from).collect();
+        // Every row is written with a trailing newline, which leaves an empty tail.
+        rows.pop();
+        Ok(Some(Self { cursor, rows }))
+    }
+
+    pub fn write<'a, I>(path: &Path, cursor: &Position, rows: I) -> Result<(), Error>
+    where
+        I: Iterator<Item = &'a [u8]>,
+    {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        {
+            let mut file = fs::File::create(&tmp)?;
+            writeln!(file, "{}", MAGIC)?;
+            writeln!(file, "{} {}", cursor.x, cursor.y)?;
+            for row in rows {
+                file.write_all(row)?;
+                file.write_all(b"\n")?;
+            }
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, path)
+    }
+
+    pub fn remove(path: &Path) -> Result<(), Error> {
+        match fs::remove_file(path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,8 +1,8 @@
+use crate::backend::{Backend, TermionBackend};
+use crate::frame::{Frame, Style};
 use crate::Position;
-use std::io::{self, stdout, Write};
+use std::time::Duration;
 use termion::event::Key;
-use termion::input::TermRead;
-use termion::raw::{IntoRawMode, RawTerminal};
 use termion::color;
 
 pub struct Size {
@@ -10,20 +10,41 @@ pub struct Size {
     pub height: u16,
 }
 
-pub struct Terminal {
+pub struct Terminal<B: Backend = TermionBackend> {
+    backend: B,
     size: Size,
-    _stdout: RawTerminal<std::io::Stdout>,
+    // Drawing goes into `back`; `flush` sends only what differs from
+    // `front`, the frame last written to the screen.
+    back: Frame,
+    front: Option<Frame>,
+    pen: Position,
+    style: Style,
+    cursor: Option<Position>,
+    shown: Option<Position>,
 }
 
 impl Terminal {
     pub fn default() -> Result<Self, std::io::Error> {
-        let size = termion::terminal_size()?;
+        Self::new(TermionBackend::new()?)
+    }
+}
+
+impl<B: Backend> Terminal<B> {
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(backend: B) -> Result<Self, std::io::Error> {
+        let (width, height) = backend.size()?;
         Ok(Self {
+            backend,
             size: Size {
-                width: size.0,
-                height: size.1.saturating_sub(2),
+                width: width as u16,
+                height: (height as u16).saturating_sub(2),
             },
-            _stdout: stdout().into_raw_mode()?,
+            back: Frame::new(width, height),
+            front: None,
+            pen: Position::default(),
+            style: Style::default(),
+            cursor: None,
+            shown: None,
         })
     }
 
@@ -31,57 +52,103 @@ impl Terminal {
         &self.size
     }
 
-    pub fn clear_screen() {
-        print!("{}", termion::clear::All);
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
     }
 
     #[allow(clippy::cast_possible_truncation)]
-    pub fn cursor_position(position: &Position) {
-        let Position{mut x, mut y} = position;
-        let x = x.saturating_add(1);
-        let y = y.saturating_add(1);
-        let x = x as u16;
-        let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+    pub fn update_size(&mut self) -> Result<(), std::io::Error> {
+        let (width, height) = self.backend.size()?;
+        self.size = Size {
+            width: width as u16,
+            height: (height as u16).saturating_sub(2),
+        };
+        if self.back.width() != width || self.back.height() != height {
+            self.back = Frame::new(width, height);
+        }
+        self.front = None;
+        Ok(())
+    }
+
+    // Hands the terminal back to the shell and stops the process. Returns
+    // once the shell resumes us, with the size re-queried.
+    pub fn suspend(&mut self) -> Result<(), std::io::Error> {
+        self.backend.suspend()?;
+        self.update_size()
     }
 
-    pub fn flush() -> Result<(), std::io::Error> {
-        io::stdout().flush()
+    pub fn clear_screen(&mut self) {
+        self.back.clear();
     }
 
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+    pub fn cursor_position(&mut self, position: &Position) {
+        self.pen = *position;
+        if self.cursor.is_some() {
+            self.cursor = Some(*position);
+        }
+    }
+
+    // Writes the frame to the screen: only the cells that changed since the
+    // last flush, in a single write.
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        if let Some(front) = &self.front {
+            if self.cursor == self.shown && self.back.changes(front).next().is_none() {
+                return Ok(());
             }
         }
+        self.backend.draw(&self.back, self.front.as_ref())?;
+        self.backend.set_cursor(self.cursor)?;
+        self.backend.flush()?;
+        self.front = Some(self.back.clone());
+        self.shown = self.cursor;
+        Ok(())
+    }
+
+    pub fn read_key(&mut self, timeout: Duration) -> Result<Option<Key>, std::io::Error> {
+        self.backend.read_key(timeout)
+    }
+
+    pub fn cursor_hide(&mut self) {
+        self.cursor = None;
+    }
+
+    pub fn cursor_show(&mut self) {
+        self.cursor = Some(self.pen);
     }
 
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    pub fn clear_current_line(&mut self) {
+        self.back.clear_line(self.pen.y);
     }
 
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+    pub fn print(&mut self, text: &str) {
+        self.pen.x = self.back.print(self.pen.x, self.pen.y, text, self.style);
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine);
+    pub fn println(&mut self, text: &str) {
+        self.print(text);
+        self.pen = Position {
+            x: 0,
+            y: self.pen.y.saturating_add(1),
+        };
     }
 
-    pub fn set_bg_color(color: color::Rgb) {
-        print!("{}", color::Bg(color));
+    pub fn set_bg_color(&mut self, color: color::Rgb) {
+        self.style.bg = Some(color);
     }
 
-    pub fn reset_bg_color() {
-        print!("{}", color::Bg(color::Reset));
+    pub fn reset_bg_color(&mut self) {
+        self.style.bg = None;
     }
 
-    pub fn set_fg_color(color: color::Rgb) {
-        print!("{}", color::Fg(color));
+    pub fn set_fg_color(&mut self, color: color::Rgb) {
+        self.style.fg = Some(color);
     }
 
-    pub fn reset_fg_color() {
-        print!("{}", color::Fg(color::Reset));
+    pub fn reset_fg_color(&mut self) {
+        self.style.fg = None;
     }
 }
@@ -0,0 +1,57 @@
+// Invisible characters and what shows them when whitespace is visible:
+// tabs, non-breaking spaces, zero-width characters, byte order marks and
+// the controls that change the direction of bidirectional text.
+const GLYPHS: &[(char, char)] = &[
+    ('\t', '→'),
+    ('\u{a0}', '⍽'),
+    ('\u{2007}', '⍽'),
+    ('\u{202f}', '⍽'),
+    ('\u{ad}', '¬'),
+    ('\u{200b}', '¦'),
+    ('\u{2060}', '¦'),
+    ('\u{feff}', '◊'),
+    ('\u{61c}', '⇄'),
+    ('\u{200e}', '⇄'),
+    ('\u{200f}', '⇄'),
+    ('\u{202a}', '⇄'),
+    ('\u{202b}', '⇄'),
+    ('\u{202c}', '⇄'),
+    ('\u{202d}', '⇄'),
+    ('\u{202e}', '⇄'),
+    ('\u{2066}', '⇄'),
+    ('\u{2067}', '⇄'),
+    ('\u{2068}', '⇄'),
+    ('\u{2069}', '⇄'),
+];
+
+// What to draw in place of `grapheme` when whitespace is visible, if it is
+// invisible. Spaces only get one at the end of a row, where they are
+// `trailing`, to keep ordinary text readable.
+pub fn glyph(grapheme: &str, trailing: bool) -> Option<char> {
+    let mut chars = grapheme.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() {
+        return None;
+    }
+    if c == ' ' {
+        return trailing.then_some('·');
+    }
+    GLYPHS.iter().find(|&&(invisible, _)| invisible == c).map(|&(_, glyph)| glyph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs() {
+        assert_eq!(glyph("\t", false), Some('→'));
+        assert_eq!(glyph(" ", false), None);
+        assert_eq!(glyph(" ", true), Some('·'));
+        assert_eq!(glyph("\u{a0}", false), Some('⍽'));
+        assert_eq!(glyph("\u{feff}", false), Some('◊'));
+        assert_eq!(glyph("\u{202e}", false), Some('⇄'));
+        assert_eq!(glyph("a", true), None);
+        assert_eq!(glyph("e\u{301}", false), None);
+    }
+}
@@ -0,0 +1,72 @@
+use crate::row;
+use crate::Row;
+use std::cmp;
+
+// Where each screen line of `row` starts, as grapheme indices, when it is
+// wrapped to `width` columns and continuation lines give up `indent` of
+// them to the marker. Tabs take up to `tab_width` columns, as they would
+// unwrapped. Lines break after the last whitespace that fits, or mid-word
+// when one word fills the whole line.
+//
+// A row that exactly fills its last line gets an empty line after it, so
+// the cursor at the end of the row still has a place on screen.
+#[allow(clippy::integer_arithmetic, clippy::indexing_slicing)]
+pub fn breaks(row: &Row, width: usize, indent: usize, tab_width: usize) -> Vec<usize> {
+    let mut starts = vec![0];
+    let len = row.len();
+    // columns[x] is the screen column grapheme x starts at.
+    let mut columns = Vec::with_capacity(len + 1);
+    let mut column = 0;
+    for x in 0..len {
+        columns.push(column);
+        column += row::width(row.slice(x, x + 1), column, tab_width);
+    }
+    columns.push(column);
+    let mut start = 0;
+    loop {
+        let room = if start == 0 { width } else { width.saturating_sub(indent) };
+        let limit = columns[start] + room.max(1);
+        if columns[len] < limit {
+            return starts;
+        }
+        let end = cmp::max(columns.partition_point(|&column| column <= limit) - 1, start + 1);
+        let after_space = (start + 1..=end)
+            .rev()
+            .find(|&x| row.slice(x - 1, x).chars().all(char::is_whitespace));
+        start = after_space.unwrap_or(end);
+        starts.push(start);
+    }
+}
+
+// Which of the screen lines starting at `breaks` holds column `x`.
+pub fn line_of(breaks: &[usize], x: usize) -> usize {
+    breaks.partition_point(|&start| start <= x).saturating_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breaks_at_word_boundaries() {
+        let row = Row::from("the quick brown fox");
+        assert_eq!(breaks(&row, 10, 2, 4), vec![0, 10, 16]);
+        assert_eq!(breaks(&row, 40, 2, 4), vec![0]);
+        assert_eq!(line_of(&[0, 10, 16], 9), 0);
+        assert_eq!(line_of(&[0, 10, 16], 10), 1);
+        assert_eq!(line_of(&[0, 10, 16], 19), 2);
+    }
+
+    #[test]
+    fn long_words_break_anywhere() {
+        let row = Row::from("abcdefghij");
+        assert_eq!(breaks(&row, 4, 1, 4), vec![0, 4, 7, 10]);
+    }
+
+    #[test]
+    fn tabs_take_their_width() {
+        let row = Row::from("\t\tab cd");
+        assert_eq!(breaks(&row, 12, 0, 4), vec![0, 5]);
+        assert_eq!(breaks(&row, 12, 0, 1), vec![0]);
+    }
+}
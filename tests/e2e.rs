@@ -0,0 +1,255 @@
+// End-to-end tests: a key script drives the editor on a headless terminal and
+// the final screen is compared with tests/snapshots/<name>.txt. Run with
+// UPDATE_SNAPSHOTS=1 to write the snapshots from the current output instead.
+
+use rustte::{
+    Config, Document, Editor, HeadlessBackend, Key, Terminal, CTRL_BACKSPACE, CTRL_DELETE, CTRL_LEFT,
+    CTRL_RIGHT,
+};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const WIDTH: usize = 40;
+const HEIGHT: usize = 12;
+
+// Characters stand for themselves; `<...>` names any other key: <C-x>,
+// <A-x>, <Up>, <Down>, <Left>, <Right>, <C-Left>, <C-Right>, <PageUp>,
+// <PageDown>, <Home>, <End>, <Enter>, <Tab>, <S-Tab>, <Esc>, <BS>, <C-BS>,
+// <Del>, <C-Del>, <C-Space> and <lt> for '<'.
+fn keys(script: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = script;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            let end = rest.find('>').expect("unterminated key name");
+            keys.push(named_key(&rest[1..end]));
+            rest = &rest[end + 1..];
+        } else {
+            keys.push(Key::Char(c));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    keys
+}
+
+fn named_key(name: &str) -> Key {
+    match name {
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "Enter" => Key::Char('\n'),
+        "Tab" => Key::Char('\t'),
+        "S-Tab" => Key::BackTab,
+        "Esc" => Key::Esc,
+        "BS" => Key::Backspace,
+        "Del" => Key::Delete,
+        "C-Left" => CTRL_LEFT,
+        "C-Right" => CTRL_RIGHT,
+        "C-BS" => CTRL_BACKSPACE,
+        "C-Del" => CTRL_DELETE,
+        "C-Space" => Key::Null,
+        "lt" => Key::Char('<'),
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next(), chars.next(), chars.next()) {
+                (Some('C'), Some('-'), Some(c), None) => Key::Ctrl(c),
+                (Some('A'), Some('-'), Some(c), None) => Key::Alt(c),
+                _ => panic!("unknown key <{}>", name),
+            }
+        }
+    }
+}
+
+// Runs `script` against a document holding `lines` and renders the screen
+// with the cursor marked by a trailing line.
+fn screen(lines: &[&str], script: &str) -> String {
+    let lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    let backend = HeadlessBackend::new(WIDTH, HEIGHT, keys(script));
+    let terminal = Terminal::new(backend).unwrap();
+    let mut editor = Editor::new(terminal, Document::from_lines(&lines), Config::default());
+    // The script running out ends the session with an error.
+    assert!(editor.run().is_err());
+    let backend = editor.terminal().backend();
+    let mut screen = backend.lines().join("\n");
+    match backend.cursor() {
+        Some(cursor) => screen.push_str(&format!("\n--- cursor {},{}\n", cursor.x, cursor.y)),
+        None => screen.push_str("\n--- cursor hidden\n"),
+    }
+    screen
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "snapshots", &format!("{}.txt", name)]
+        .iter()
+        .collect();
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("no snapshot at {}, run with UPDATE_SNAPSHOTS=1", path.display()));
+    assert!(
+        expected == actual,
+        "screen differs from {}:\n{}\nexpected:\n{}",
+        path.display(),
+        actual,
+        expected
+    );
+}
+
+fn numbered(count: usize) -> Vec<String> {
+    (1..=count).map(|n| format!("line {}", n)).collect()
+}
+
+#[test]
+fn welcome() {
+    assert_snapshot("welcome", &screen(&[], ""));
+}
+
+#[test]
+fn typing() {
+    assert_snapshot("typing", &screen(&[], "Hello, world!<Enter>second line<Left><Left><BS>"));
+}
+
+#[test]
+fn backspace_joins_lines() {
+    assert_snapshot("backspace_joins_lines", &screen(&["first", "second"], "<Down><BS>"));
+}
+
+#[test]
+fn delete_at_end_of_row_joins_lines() {
+    assert_snapshot("delete_joins_lines", &screen(&["first", "second"], "<End><Del>"));
+}
+
+#[test]
+fn page_down() {
+    let lines = numbered(50);
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    assert_snapshot("page_down", &screen(&lines, "<PageDown><PageDown>"));
+}
+
+#[test]
+fn page_down_stops_at_the_end() {
+    let lines = numbered(15);
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    assert_snapshot("page_down_end", &screen(&lines, "<PageDown><PageDown><PageDown>"));
+}
+
+#[test]
+fn page_up() {
+    let lines = numbered(50);
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    assert_snapshot("page_up", &screen(&lines, "<PageDown><PageDown><PageDown><PageUp>"));
+}
+
+#[test]
+fn scrolls_down_with_the_cursor() {
+    let lines = numbered(30);
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let script = "<Down>".repeat(12);
+    assert_snapshot("scroll_down", &screen(&lines, &script));
+}
+
+#[test]
+fn scrolls_right_on_long_lines() {
+    let long = "0123456789".repeat(6);
+    assert_snapshot("scroll_right", &screen(&[&long, "short"], "<End>"));
+}
+
+#[test]
+fn search() {
+    assert_snapshot(
+        "search",
+        &screen(&["alpha", "beta", "gamma delta"], "<C-f>delta<Enter>"),
+    );
+}
+
+#[test]
+fn search_not_found() {
+    assert_snapshot("search_not_found", &screen(&["alpha"], "<C-f>omega<Enter>"));
+}
+
+#[test]
+fn undo_and_redo() {
+    assert_snapshot(
+        "undo_redo",
+        &screen(&["text"], "<End> one two<C-u><C-u><C-r>"),
+    );
+}
+
+#[test]
+fn quit_warns_about_unsaved_changes() {
+    assert_snapshot("quit_dirty", &screen(&["text"], "x<C-q>"));
+}
+
+#[test]
+fn enter_keeps_and_deepens_indentation() {
+    assert_snapshot(
+        "auto_indent",
+        &screen(&["fn main() {}"], "<End><Left><Enter>if x {<Enter>y<Enter>}"),
+    );
+}
+
+#[test]
+fn backspace_removes_an_indent_level() {
+    assert_snapshot("smart_backspace", &screen(&["          x"], "<End><Left><BS><BS>"));
+}
+
+#[test]
+fn tab_indents_the_selected_lines() {
+    assert_snapshot(
+        "block_indent",
+        &screen(&["one", "two", "three"], "<Right><C-Space><Down><Tab><Tab><Down><S-Tab>"),
+    );
+}
+
+#[test]
+fn move_and_duplicate_lines() {
+    assert_snapshot("move_lines", &screen(&["one", "two", "three"], "<Down><A-k><A-d>"));
+}
+
+#[test]
+fn brackets_and_quotes_pair_up() {
+    assert_snapshot("auto_pairs", &screen(&[], "call(\"x\");[<BS>don't"));
+}
+
+#[test]
+fn word_movement_and_deletion() {
+    assert_snapshot(
+        "word_motion",
+        &screen(
+            &["let total = price * count;", "  next_line"],
+            "<C-Right><C-Right><C-Del><C-Right><C-Right><C-Right><C-BS><C-Left><C-Left>",
+        ),
+    );
+}
+
+#[test]
+fn soft_wrap_breaks_between_words() {
+    let prose = "Soft wrap keeps long lines of prose on screen by breaking them between words.";
+    assert_snapshot("soft_wrap", &screen(&[prose, "end"], "<A-w><Down><Right><Right><Down><Down>"));
+}
+
+#[test]
+fn soft_wrap_scrolls_by_screen_line() {
+    let long = "word ".repeat(60);
+    assert_snapshot("soft_wrap_scroll", &screen(&[&long, "last"], &format!("<A-w>{}", "<Down>".repeat(10))));
+}
+
+#[test]
+fn reflow_refills_the_paragraph() {
+    assert_snapshot("reflow", &screen(&["> one two", "> three", ">", "> four"], "<A-q>"));
+}
+
+#[test]
+fn visible_whitespace_marks_tabs_and_invisibles() {
+    let lines = ["\tindented  ", "no\u{a0}break", "\u{feff}zero\u{200b}width"];
+    assert_snapshot("visible_whitespace", &screen(&lines, "<A-.><Down><Down>"));
+}